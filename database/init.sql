@@ -2,27 +2,110 @@
 
 CREATE DATABASE rustmerce;
 
+CREATE TABLE categories (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    parent_id INT,
+    -- position among siblings
+    position INT NOT NULL DEFAULT 0,
+    slug TEXT NOT NULL,
+    -- slugs of the ancestors and the category joined with `/`, e.g. `electronics/laptops`
+    path TEXT NOT NULL,
+    meta_title TEXT,
+    meta_description TEXT,
+    CONSTRAINT fk_parent FOREIGN KEY (parent_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+-- names are unique among siblings, top level categories are siblings too
+CREATE UNIQUE INDEX categories_sibling_name ON categories (COALESCE(parent_id, 0), lower(name));
+CREATE UNIQUE INDEX categories_sibling_slug ON categories (COALESCE(parent_id, 0), slug);
+CREATE UNIQUE INDEX categories_path ON categories (path);
+
+-- attributes products in the category and its subcategories have
+CREATE TABLE category_attributes (
+    category_id INT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('string', 'integer', 'decimal', 'boolean', 'enum', 'quantity')),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    -- allowed values of enum attributes
+    options TEXT[] NOT NULL DEFAULT '{}',
+    -- unit of quantity attributes, e.g. `GB`
+    unit TEXT,
+    PRIMARY KEY (category_id, name),
+    CONSTRAINT fk_category FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
 CREATE TABLE products (
     id SERIAL PRIMARY KEY,
     name TEXT NOT NULL,
     price FLOAT NOT NULL,
-    category_id INT,
     status TEXT NOT NULL DEFAULT 'Draft',
-    CONSTRAINT fk_category FOREIGN KEY (category_id) REFERENCES categories(id)
+    slug TEXT NOT NULL UNIQUE,
+    -- values of attributes defined by the product's categories,
+    -- quantities are stored as `{"value": 16, "unit": "GB"}`
+    attributes JSONB NOT NULL DEFAULT '{}'
+);
+
+-- speeds up attribute equality filters, which are containment queries
+CREATE INDEX products_attributes ON products USING GIN (attributes jsonb_path_ops);
+
+-- products can be in many categories, one of them is primary (e.g. used for breadcrumbs)
+CREATE TABLE product_categories (
+    product_id INT NOT NULL,
+    category_id INT NOT NULL,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    -- sort position of the product in the category listing
+    position INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (product_id, category_id),
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE,
+    CONSTRAINT fk_category FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+-- a product can have only one primary category
+CREATE UNIQUE INDEX product_categories_single_primary ON product_categories (product_id) WHERE is_primary;
+
+CREATE INDEX product_categories_category ON product_categories (category_id, position);
+
+-- previous slugs of renamed products, they redirect to the current slug
+CREATE TABLE product_slug_redirects (
+    slug TEXT PRIMARY KEY,
+    product_id INT NOT NULL,
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
 );
 
 CREATE TABLE assets (
     id SERIAL PRIMARY KEY,
     filename TEXT NOT NULL,
+    -- SHA-256 of the file content, files are shared between assets with the same hash
+    hash TEXT NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'document')),
+    -- sanitized name the file was uploaded with
+    original_filename TEXT,
     product_id INT NOT NULL,
+    position INT NOT NULL DEFAULT 0,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    alt_text TEXT,
     CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
 );
 
-CREATE TABLE categories (
-    id SERIAL PRIMARY KEY,
-    name TEXT NOT NULL,
-    parent_id INT,
-    CONSTRAINT fk_parent FOREIGN KEY (parent_id) REFERENCES category(id) ON DELETE CASCADE
+-- a product can have only one primary asset
+CREATE UNIQUE INDEX assets_single_primary ON assets (product_id) WHERE is_primary;
+
+CREATE INDEX assets_hash ON assets (hash);
+CREATE INDEX assets_filename ON assets (filename);
+
+-- direct uploads, the file is sent with a one-time token and turned into an asset on completion
+CREATE TABLE asset_uploads (
+    token TEXT PRIMARY KEY,
+    product_id INT NOT NULL,
+    content_type TEXT NOT NULL,
+    original_filename TEXT,
+    size BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'uploading', 'uploaded', 'completing')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- set when the completion starts, so uploads stuck in it can be expired
+    completing_since TIMESTAMPTZ,
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
 );
 
 -- util procedures
@@ -35,4 +118,15 @@ AS $$
         UNION ALL
         SELECT c.id  FROM categories AS c, parent_category AS pc WHERE c.parent_id = pc.id
     ) SELECT * FROM parent_category;
-$$ LANGUAGE SQL;
\ No newline at end of file
+$$ LANGUAGE SQL;
+
+-- get_ancestors returns the category and all categories higher in hierarchy,
+-- depth is 0 for the category itself and grows towards the root.
+CREATE FUNCTION get_ancestors(category_id int) RETURNS TABLE(id int, depth int)
+AS $$
+    WITH RECURSIVE ancestor AS (
+        SELECT id, parent_id, 0 AS depth FROM categories WHERE id = $1
+        UNION ALL
+        SELECT c.id, c.parent_id, a.depth + 1 FROM categories AS c, ancestor AS a WHERE c.id = a.parent_id
+    ) SELECT id, depth FROM ancestor;
+$$ LANGUAGE SQL;
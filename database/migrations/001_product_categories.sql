@@ -0,0 +1,27 @@
+-- moves products.category_id into the product_categories join table,
+-- for databases created before products could be in many categories
+
+BEGIN;
+
+CREATE TABLE product_categories (
+    product_id INT NOT NULL,
+    category_id INT NOT NULL,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    position INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (product_id, category_id),
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE,
+    CONSTRAINT fk_category FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX product_categories_single_primary ON product_categories (product_id) WHERE is_primary;
+
+CREATE INDEX product_categories_category ON product_categories (category_id, position);
+
+-- the only category a product had becomes its primary one
+INSERT INTO product_categories (product_id, category_id, is_primary, position)
+SELECT id, category_id, TRUE, ROW_NUMBER() OVER (PARTITION BY category_id ORDER BY id) - 1
+FROM products WHERE category_id IS NOT NULL;
+
+ALTER TABLE products DROP COLUMN category_id;
+
+COMMIT;
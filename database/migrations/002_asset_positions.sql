@@ -0,0 +1,19 @@
+-- adds positions, primary flags and alt texts to assets,
+-- for databases created before assets could be ordered
+
+BEGIN;
+
+ALTER TABLE assets
+    ADD COLUMN position INT NOT NULL DEFAULT 0,
+    ADD COLUMN is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN alt_text TEXT;
+
+-- existing assets keep the order they were added in
+UPDATE assets AS a SET position = o.position
+FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY product_id ORDER BY id) - 1 AS position FROM assets) AS o
+WHERE o.id = a.id;
+
+-- a product can have only one primary asset
+CREATE UNIQUE INDEX assets_single_primary ON assets (product_id) WHERE is_primary;
+
+COMMIT;
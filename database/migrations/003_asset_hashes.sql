@@ -0,0 +1,15 @@
+-- adds content hashes to assets, for databases created before files were stored by hash
+
+BEGIN;
+
+ALTER TABLE assets ADD COLUMN hash TEXT;
+
+-- the content of files stored before is unknown, their placeholder never matches
+-- a SHA-256, so new uploads aren't deduplicated into them
+UPDATE assets SET hash = 'unknown:' || filename;
+
+ALTER TABLE assets ALTER COLUMN hash SET NOT NULL;
+
+CREATE INDEX assets_hash ON assets (hash);
+
+COMMIT;
@@ -0,0 +1,13 @@
+-- adds kinds and original filenames to assets, for databases created before documents
+-- could be attached to products
+
+BEGIN;
+
+-- every asset stored before is an image
+ALTER TABLE assets
+    ADD COLUMN kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'document')),
+    ADD COLUMN original_filename TEXT;
+
+CREATE INDEX assets_filename ON assets (filename);
+
+COMMIT;
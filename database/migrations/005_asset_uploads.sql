@@ -0,0 +1,17 @@
+-- adds direct uploads, for databases created before assets could be uploaded with a token
+
+BEGIN;
+
+-- direct uploads, the file is sent with a one-time token and turned into an asset on completion
+CREATE TABLE asset_uploads (
+    token TEXT PRIMARY KEY,
+    product_id INT NOT NULL,
+    content_type TEXT NOT NULL,
+    original_filename TEXT,
+    size BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'uploading', 'uploaded', 'completing')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
+);
+
+COMMIT;
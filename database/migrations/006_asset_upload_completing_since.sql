@@ -0,0 +1,11 @@
+-- records when the completion of a direct upload started,
+-- so uploads stuck in it (e.g. after a crash) can be expired
+
+BEGIN;
+
+ALTER TABLE asset_uploads ADD COLUMN completing_since TIMESTAMPTZ;
+
+-- uploads already being completed are treated as if they had just started
+UPDATE asset_uploads SET completing_since = now() WHERE status = 'completing';
+
+COMMIT;
@@ -0,0 +1,17 @@
+-- makes category names unique among siblings, for databases created before categories
+-- could be created and renamed through the api
+
+BEGIN;
+
+-- siblings sharing a name keep it apart with their id
+UPDATE categories AS c SET name = c.name || ' (' || c.id || ')'
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY COALESCE(parent_id, 0), lower(name) ORDER BY id) AS n
+    FROM categories
+) AS d
+WHERE d.id = c.id AND d.n > 1;
+
+-- names are unique among siblings, top level categories are siblings too
+CREATE UNIQUE INDEX categories_sibling_name ON categories (COALESCE(parent_id, 0), lower(name));
+
+COMMIT;
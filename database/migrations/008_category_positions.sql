@@ -0,0 +1,16 @@
+-- adds positions among siblings to categories, for databases created before categories
+-- could be moved
+
+BEGIN;
+
+ALTER TABLE categories ADD COLUMN position INT NOT NULL DEFAULT 0;
+
+-- existing categories are listed in the order they were created in
+UPDATE categories AS c SET position = o.position
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY COALESCE(parent_id, 0) ORDER BY id) - 1 AS position
+    FROM categories
+) AS o
+WHERE o.id = c.id;
+
+COMMIT;
@@ -0,0 +1,16 @@
+-- adds get_ancestors, for databases created before categories had paths
+
+BEGIN;
+
+-- get_ancestors returns the category and all categories higher in hierarchy,
+-- depth is 0 for the category itself and grows towards the root.
+CREATE FUNCTION get_ancestors(category_id int) RETURNS TABLE(id int, depth int)
+AS $$
+    WITH RECURSIVE ancestor AS (
+        SELECT id, parent_id, 0 AS depth FROM categories WHERE id = $1
+        UNION ALL
+        SELECT c.id, c.parent_id, a.depth + 1 FROM categories AS c, ancestor AS a WHERE c.id = a.parent_id
+    ) SELECT id, depth FROM ancestor;
+$$ LANGUAGE SQL;
+
+COMMIT;
@@ -0,0 +1,48 @@
+-- adds slugs, paths and SEO metadata to categories,
+-- for databases created before categories had them
+
+BEGIN;
+
+-- slugify mirrors slug::slugify for the existing names
+CREATE FUNCTION pg_temp.slugify(name TEXT, fallback TEXT) RETURNS TEXT
+AS $$
+    SELECT COALESCE(
+        NULLIF(trim(BOTH '-' FROM left(regexp_replace(
+            lower(translate(name, 'ąćęłńóśźżĄĆĘŁŃÓŚŹŻ', 'acelnoszzacelnoszz')),
+            '[^a-z0-9]+', '-', 'g'
+        ), 100)), ''),
+        fallback
+    );
+$$ LANGUAGE SQL;
+
+ALTER TABLE categories
+    ADD COLUMN slug TEXT,
+    ADD COLUMN path TEXT,
+    ADD COLUMN meta_title TEXT,
+    ADD COLUMN meta_description TEXT;
+
+UPDATE categories SET slug = pg_temp.slugify(name, 'category');
+
+-- siblings with the same slug keep it unique with their id
+UPDATE categories AS c SET slug = left(c.slug, 90) || '-' || c.id
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY COALESCE(parent_id, 0), slug ORDER BY id) AS n
+    FROM categories
+) AS d
+WHERE d.id = c.id AND d.n > 1;
+
+WITH RECURSIVE paths AS (
+    SELECT id, slug AS path FROM categories WHERE parent_id IS NULL
+    UNION ALL
+    SELECT c.id, p.path || '/' || c.slug FROM categories AS c JOIN paths AS p ON c.parent_id = p.id
+)
+UPDATE categories AS c SET path = p.path FROM paths AS p WHERE p.id = c.id;
+
+ALTER TABLE categories
+    ALTER COLUMN slug SET NOT NULL,
+    ALTER COLUMN path SET NOT NULL;
+
+CREATE UNIQUE INDEX categories_sibling_slug ON categories (COALESCE(parent_id, 0), slug);
+CREATE UNIQUE INDEX categories_path ON categories (path);
+
+COMMIT;
@@ -0,0 +1,37 @@
+-- adds slugs to products and keeps previous slugs of renamed products,
+-- for databases created before products had them
+
+BEGIN;
+
+-- slugify mirrors slug::slugify for the existing names
+CREATE FUNCTION pg_temp.slugify(name TEXT, fallback TEXT) RETURNS TEXT
+AS $$
+    SELECT COALESCE(
+        NULLIF(trim(BOTH '-' FROM left(regexp_replace(
+            lower(translate(name, 'ąćęłńóśźżĄĆĘŁŃÓŚŹŻ', 'acelnoszzacelnoszz')),
+            '[^a-z0-9]+', '-', 'g'
+        ), 100)), ''),
+        fallback
+    );
+$$ LANGUAGE SQL;
+
+ALTER TABLE products ADD COLUMN slug TEXT;
+
+UPDATE products SET slug = pg_temp.slugify(name, 'product');
+
+-- products with the same slug keep it unique with their id
+UPDATE products AS p SET slug = left(p.slug, 90) || '-' || p.id
+FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY slug ORDER BY id) AS n FROM products) AS d
+WHERE d.id = p.id AND d.n > 1;
+
+ALTER TABLE products ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE products ADD CONSTRAINT products_slug_key UNIQUE (slug);
+
+-- previous slugs of renamed products, they redirect to the current slug
+CREATE TABLE product_slug_redirects (
+    slug TEXT PRIMARY KEY,
+    product_id INT NOT NULL,
+    CONSTRAINT fk_product FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
+);
+
+COMMIT;
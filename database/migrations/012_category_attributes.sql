@@ -0,0 +1,20 @@
+-- adds attribute definitions to categories and attribute values to products,
+-- for databases created before products had attributes
+
+BEGIN;
+
+-- attributes products in the category and its subcategories have
+CREATE TABLE category_attributes (
+    category_id INT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('string', 'integer', 'decimal', 'boolean', 'enum')),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    -- allowed values of enum attributes
+    options TEXT[] NOT NULL DEFAULT '{}',
+    PRIMARY KEY (category_id, name),
+    CONSTRAINT fk_category FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+ALTER TABLE products ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+COMMIT;
@@ -0,0 +1,14 @@
+-- adds quantity attributes with a unit and indexes attribute values for filtering
+
+BEGIN;
+
+ALTER TABLE category_attributes
+    DROP CONSTRAINT category_attributes_kind_check,
+    ADD CONSTRAINT category_attributes_kind_check
+        CHECK (kind IN ('string', 'integer', 'decimal', 'boolean', 'enum', 'quantity')),
+    ADD COLUMN unit TEXT;
+
+-- speeds up attribute equality filters, which are containment queries
+CREATE INDEX products_attributes ON products USING GIN (attributes jsonb_path_ops);
+
+COMMIT;
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use tokio_postgres::Row;
+use validator::Validate;
 
+pub mod attributes;
 pub mod handlers;
 pub mod store;
 
@@ -9,6 +11,11 @@ pub struct Category {
     pub id: i32,
     pub name: String,
     pub parent_id: Option<i32>,
+    pub position: i32,
+    pub slug: String,
+    pub path: String,
+    pub meta_title: Option<String>,
+    pub meta_description: Option<String>,
     pub children: Vec<Category>,
 }
 
@@ -20,7 +27,66 @@ impl TryFrom<&Row> for Category {
             id: row.try_get("id")?,
             name: row.try_get("name")?,
             parent_id: row.try_get("parent_id")?,
+            position: row.try_get("position")?,
+            slug: row.try_get("slug")?,
+            path: row.try_get("path")?,
+            meta_title: row.try_get("meta_title")?,
+            meta_description: row.try_get("meta_description")?,
             children: Vec::new(),
         })
     }
 }
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CategoryInsertable {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+
+    pub parent_id: Option<i32>,
+
+    // slug is generated from the name when it's missing.
+    #[validate(custom = "crate::slug::validate_slug")]
+    pub slug: Option<String>,
+
+    #[validate(length(max = 255))]
+    pub meta_title: Option<String>,
+
+    #[validate(length(max = 500))]
+    pub meta_description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CategoryUpdatable {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+
+    // slug is kept when it's missing, so renaming doesn't change category urls.
+    #[validate(custom = "crate::slug::validate_slug")]
+    pub slug: Option<String>,
+
+    #[validate(length(max = 255))]
+    pub meta_title: Option<String>,
+
+    #[validate(length(max = 500))]
+    pub meta_description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CategoryMove {
+    // parent_id is the new parent, None moves the category to the root.
+    pub parent_id: Option<i32>,
+
+    // position among the new siblings, the category is appended when it's missing.
+    #[validate(range(min = 0))]
+    pub position: Option<i32>,
+}
+
+// CategoryDeletion says what happens to products of a deleted category and its subcategories.
+pub enum CategoryDeletion {
+    // Refuse keeps the category if any products are attached.
+    Refuse,
+    // Reassign moves the products to the given category.
+    Reassign(i32),
+    // Cascade deletes the products together with the category.
+    Cascade,
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use tokio_postgres::Row;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeKind {
+    String,
+    Integer,
+    Decimal,
+    Boolean,
+    Enum,
+    // Quantity is a number with the unit of the definition, e.g. 16 GB.
+    Quantity,
+}
+
+impl AttributeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Integer => "integer",
+            Self::Decimal => "decimal",
+            Self::Boolean => "boolean",
+            Self::Enum => "enum",
+            Self::Quantity => "quantity",
+        }
+    }
+
+    fn parse(kind: &str) -> Self {
+        match kind {
+            "integer" => Self::Integer,
+            "decimal" => Self::Decimal,
+            "boolean" => Self::Boolean,
+            "enum" => Self::Enum,
+            "quantity" => Self::Quantity,
+            _ => Self::String,
+        }
+    }
+}
+
+// AttributeDefinition describes an attribute products in the category (and its subcategories) have.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributeDefinition {
+    // category_id is the category the attribute is defined in, it can be an ancestor.
+    pub category_id: i32,
+    pub name: String,
+    pub kind: AttributeKind,
+    pub required: bool,
+    // options are the allowed values of enum attributes.
+    pub options: Vec<String>,
+    // unit is the unit of quantity attributes.
+    pub unit: Option<String>,
+}
+
+impl TryFrom<&Row> for AttributeDefinition {
+    type Error = tokio_pg_mapper::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let kind: &str = row.try_get("kind")?;
+
+        Ok(AttributeDefinition {
+            category_id: row.try_get("category_id")?,
+            name: row.try_get("name")?,
+            kind: AttributeKind::parse(kind),
+            required: row.try_get("required")?,
+            options: row.try_get("options")?,
+            unit: row.try_get("unit")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct AttributeDefinitionInsertable {
+    // names are used as keys of product attributes and in filters, so they're slug-like
+    #[validate(length(min = 1, max = 64), custom = "validate_attribute_name")]
+    pub name: String,
+
+    pub kind: AttributeKind,
+
+    #[serde(default)]
+    pub required: bool,
+
+    #[serde(default)]
+    pub options: Vec<String>,
+
+    #[validate(length(min = 1, max = 32))]
+    pub unit: Option<String>,
+}
+
+fn validate_attribute_name(name: &str) -> Result<(), ValidationError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("attribute_name"))
+    }
+}
+
+fn attribute_error(code: &'static str, definition: &AttributeDefinition) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.add_param(Cow::from("attribute"), &definition.name);
+    error.add_param(Cow::from("kind"), &definition.kind);
+
+    match definition.kind {
+        AttributeKind::Enum => error.add_param(Cow::from("options"), &definition.options),
+        AttributeKind::Quantity => error.add_param(Cow::from("unit"), &definition.unit),
+        _ => {}
+    }
+
+    error
+}
+
+// quantity_value returns the number of a quantity, given either as a plain number
+// in the unit of the definition or as `{"value": 16, "unit": "GB"}`.
+fn quantity_value(definition: &AttributeDefinition, value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Object(o)
+            if o.len() == 2
+                && o.get("unit").and_then(Value::as_str) == definition.unit.as_deref() =>
+        {
+            o.get("value").and_then(Value::as_f64)
+        }
+        _ => None,
+    }
+}
+
+// is_typed_value accepts values attributes without a definition can have: strings, numbers,
+// booleans and quantities.
+fn is_typed_value(value: &Value) -> bool {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => true,
+        Value::Object(o) => {
+            o.len() == 2
+                && o.get("value").is_some_and(Value::is_number)
+                && o.get("unit").is_some_and(Value::is_string)
+        }
+        _ => false,
+    }
+}
+
+fn matches_kind(definition: &AttributeDefinition, value: &Value) -> bool {
+    match definition.kind {
+        AttributeKind::String => value.is_string(),
+        AttributeKind::Integer => value.is_i64() || value.is_u64(),
+        AttributeKind::Decimal => value.is_number(),
+        AttributeKind::Boolean => value.is_boolean(),
+        AttributeKind::Enum => value
+            .as_str()
+            .is_some_and(|v| definition.options.iter().any(|o| o == v)),
+        AttributeKind::Quantity => quantity_value(definition, value).is_some(),
+    }
+}
+
+// validate_attributes checks product attributes against the definitions of its categories,
+// errors are reported under the `attributes` field like other validation errors.
+pub fn validate_attributes(
+    definitions: &[AttributeDefinition],
+    attributes: &Map<String, Value>,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+
+    for definition in definitions {
+        match attributes.get(&definition.name) {
+            None | Some(Value::Null) if definition.required => {
+                errors.add("attributes", attribute_error("required", definition))
+            }
+            None | Some(Value::Null) => {}
+            Some(value) if !matches_kind(definition, value) => {
+                errors.add("attributes", attribute_error("invalid_type", definition))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, value) in attributes {
+        if !definitions.iter().any(|d| d.name == *name) && !is_typed_value(value) {
+            let mut error = ValidationError::new("invalid_type");
+            error.add_param(Cow::from("attribute"), name);
+            errors.add("attributes", error);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// normalize_attributes stores quantities with their unit, so they're self-describing.
+// Attributes have to be validated first.
+pub fn normalize_attributes(
+    definitions: &[AttributeDefinition],
+    attributes: &mut Map<String, Value>,
+) {
+    for definition in definitions {
+        if definition.kind != AttributeKind::Quantity {
+            continue;
+        }
+
+        if let Some(value) = attributes.get_mut(&definition.name) {
+            if let Some(n) = quantity_value(definition, value) {
+                *value = json!({ "value": n, "unit": definition.unit });
+            }
+        }
+    }
+}
@@ -1,11 +1,35 @@
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use anyhow::Context;
+use serde::Deserialize;
 use serde_json::json;
+use validator::Validate;
 
-use super::store::CategoryStore;
+use crate::{
+    product::{
+        cache::{Cache, CATEGORIES_TAG, PRODUCTS_LIST_TAG},
+        handlers::resolve_asset_urls,
+        store::ProductStore,
+    },
+    storage::Storage,
+};
+
+use super::{
+    attributes::{AttributeDefinitionInsertable, AttributeKind},
+    store::{CategoryStore, CategoryStoreError},
+    CategoryDeletion, CategoryInsertable, CategoryMove, CategoryUpdatable,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum CategoryApiError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("Validation failed")]
+    ValidationError(#[from] validator::ValidationErrors),
+
     #[error(transparent)]
     Internal(#[from] anyhow::Error),
 }
@@ -13,6 +37,8 @@ pub enum CategoryApiError {
 impl ResponseError for CategoryApiError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
+            Self::ValidationError(_) | Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -21,11 +47,33 @@ impl ResponseError for CategoryApiError {
         let mut response = HttpResponse::build(self.status_code());
 
         match self {
+            Self::ValidationError(e) => {
+                response.json(json!({"message": "Validation failed", "errors": e.errors()}))
+            }
+            Self::BadRequest(message) | Self::Conflict(message) => {
+                response.json(json!({ "message": message }))
+            }
             Self::Internal(_) => response.json(json!({ "message": "Internal server error" })),
         }
     }
 }
 
+impl From<CategoryStoreError> for CategoryApiError {
+    fn from(e: CategoryStoreError) -> Self {
+        match e {
+            CategoryStoreError::ParentNotFound
+            | CategoryStoreError::InvalidTarget
+            | CategoryStoreError::Cycle
+            | CategoryStoreError::ConflictingAttribute(_) => Self::BadRequest(e.to_string()),
+            CategoryStoreError::NameTaken
+            | CategoryStoreError::SlugTaken
+            | CategoryStoreError::AttributeExists
+            | CategoryStoreError::HasProducts(_) => Self::Conflict(e.to_string()),
+            e => Self::Internal(anyhow::Error::new(e).context("Category operation failed")),
+        }
+    }
+}
+
 async fn list_categories(
     category_store: web::Data<CategoryStore>,
 ) -> Result<HttpResponse, CategoryApiError> {
@@ -37,12 +85,38 @@ async fn list_categories(
     Ok(HttpResponse::Ok().json(categories))
 }
 
+#[derive(Deserialize)]
+struct TreeQuery {
+    // depth limits how many levels of subcategories are returned, all of them by default.
+    depth: Option<u32>,
+}
+
 async fn get_category(
     id: web::Path<i32>,
+    query: web::Query<TreeQuery>,
+    category_store: web::Data<CategoryStore>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let category = category_store
+        .get_one(id.into_inner(), query.depth)
+        .await
+        .context("Failed to get category")?;
+
+    match category {
+        Some(category) => Ok(HttpResponse::Ok().json(category)),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        }))),
+    }
+}
+
+// get_category_by_path resolves storefront urls like `/c/electronics/laptops`.
+async fn get_category_by_path(
+    path: web::Path<String>,
+    query: web::Query<TreeQuery>,
     category_store: web::Data<CategoryStore>,
 ) -> Result<HttpResponse, CategoryApiError> {
     let category = category_store
-        .get_one(id.into_inner())
+        .get_by_path(path.trim_matches('/'), query.depth)
         .await
         .context("Failed to get category")?;
 
@@ -54,10 +128,274 @@ async fn get_category(
     }
 }
 
+#[derive(Deserialize)]
+struct CategoryProductsQuery {
+    #[serde(default)]
+    include_subcategories: bool,
+}
+
+// list_category_products returns products in the category ordered for merchandising.
+async fn list_category_products(
+    id: web::Path<i32>,
+    query: web::Query<CategoryProductsQuery>,
+    category_store: web::Data<CategoryStore>,
+    product_store: web::Data<ProductStore>,
+    storage: web::Data<Storage>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let id = id.into_inner();
+
+    let category = category_store
+        .get_one(id, Some(0))
+        .await
+        .context("Failed to get category")?;
+
+    if category.is_none() {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        })));
+    }
+
+    let mut products = product_store
+        .get_by_category(id, query.include_subcategories)
+        .await
+        .context("Failed to get category products")?;
+
+    products
+        .iter_mut()
+        .for_each(|p| resolve_asset_urls(&storage, p));
+
+    Ok(HttpResponse::Ok().json(products))
+}
+
+// list_category_attributes returns attributes products in the category have,
+// including the ones inherited from its ancestors.
+async fn list_category_attributes(
+    id: web::Path<i32>,
+    category_store: web::Data<CategoryStore>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let id = id.into_inner();
+
+    let category = category_store
+        .get_one(id, Some(0))
+        .await
+        .context("Failed to get category")?;
+
+    if category.is_none() {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        })));
+    }
+
+    let attributes = category_store
+        .get_attributes(&[id])
+        .await
+        .context("Failed to get category attributes")?;
+
+    Ok(HttpResponse::Ok().json(attributes))
+}
+
+async fn add_category_attribute(
+    id: web::Path<i32>,
+    data: web::Json<AttributeDefinitionInsertable>,
+    category_store: web::Data<CategoryStore>,
+) -> Result<HttpResponse, CategoryApiError> {
+    data.validate()?;
+
+    // only enum attributes have options, and they need at least one
+    if (data.kind == AttributeKind::Enum) == data.options.is_empty() {
+        return Err(CategoryApiError::BadRequest(
+            "Options are required for enum attributes and not allowed for others".to_string(),
+        ));
+    }
+
+    if (data.kind == AttributeKind::Quantity) != data.unit.is_some() {
+        return Err(CategoryApiError::BadRequest(
+            "Unit is required for quantity attributes and not allowed for others".to_string(),
+        ));
+    }
+
+    let attribute = category_store
+        .add_attribute(id.into_inner(), data.into_inner())
+        .await?;
+
+    match attribute {
+        Some(attribute) => Ok(HttpResponse::Created().json(attribute)),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        }))),
+    }
+}
+
+async fn delete_category_attribute(
+    path: web::Path<(i32, String)>,
+    category_store: web::Data<CategoryStore>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let (id, name) = path.into_inner();
+
+    if category_store.delete_attribute(id, &name).await? {
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "message": "Attribute not found"
+        })))
+    }
+}
+
+// get_category_path returns ancestors of the category from the root down to it, e.g. for breadcrumbs.
+async fn get_category_path(
+    id: web::Path<i32>,
+    category_store: web::Data<CategoryStore>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let path = category_store
+        .get_path(id.into_inner())
+        .await
+        .context("Failed to get category path")?;
+
+    if path.is_empty() {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(path))
+}
+
+// invalidate_products purges cached products after the category tree changes,
+// products include their categories and listings count products in categories.
+async fn invalidate_products(cache: &Cache) {
+    cache.invalidate(&[CATEGORIES_TAG, PRODUCTS_LIST_TAG]).await;
+}
+
+async fn create_category(
+    data: web::Json<CategoryInsertable>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, CategoryApiError> {
+    data.validate()?;
+
+    let created = category_store.insert(data.into_inner()).await?;
+
+    invalidate_products(&cache).await;
+
+    Ok(HttpResponse::Created().json(created))
+}
+
+async fn update_category(
+    id: web::Path<i32>,
+    data: web::Json<CategoryUpdatable>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, CategoryApiError> {
+    data.validate()?;
+
+    let category = category_store
+        .update(id.into_inner(), data.into_inner())
+        .await?;
+
+    match category {
+        Some(category) => {
+            invalidate_products(&cache).await;
+
+            Ok(HttpResponse::Ok().json(category))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        }))),
+    }
+}
+
+async fn move_category(
+    id: web::Path<i32>,
+    data: web::Json<CategoryMove>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, CategoryApiError> {
+    data.validate()?;
+
+    let category = category_store
+        .move_category(id.into_inner(), data.into_inner())
+        .await?;
+
+    match category {
+        Some(category) => {
+            invalidate_products(&cache).await;
+
+            Ok(HttpResponse::Ok().json(category))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        }))),
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum DeleteMode {
+    #[default]
+    Refuse,
+    Reassign,
+    Cascade,
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    mode: DeleteMode,
+
+    // target is the category products are moved to in reassign mode.
+    target: Option<i32>,
+}
+
+// delete_category removes the category and its subcategories,
+// `?mode=refuse|reassign|cascade` decides what happens to their products.
+async fn delete_category(
+    id: web::Path<i32>,
+    query: web::Query<DeleteQuery>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, CategoryApiError> {
+    let deletion = match query.mode {
+        DeleteMode::Refuse => CategoryDeletion::Refuse,
+        DeleteMode::Reassign => match query.target {
+            Some(target) => CategoryDeletion::Reassign(target),
+            None => {
+                return Err(CategoryApiError::BadRequest(
+                    "Reassign mode requires a target category".to_string(),
+                ))
+            }
+        },
+        DeleteMode::Cascade => CategoryDeletion::Cascade,
+    };
+
+    if category_store.delete(id.into_inner(), deletion).await? {
+        invalidate_products(&cache).await;
+
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "message": "Category not found"
+        })))
+    }
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/categories")
             .route("", web::get().to(list_categories))
-            .route("{id}", web::get().to(get_category)),
+            .route("", web::post().to(create_category))
+            // registered before `{id}` routes, which would match `by-path/...` too
+            .route("by-path/{path:.+}", web::get().to(get_category_by_path))
+            .route("{id}", web::get().to(get_category))
+            .route("{id}", web::put().to(update_category))
+            .route("{id}", web::delete().to(delete_category))
+            .route("{id}/move", web::post().to(move_category))
+            .route("{id}/path", web::get().to(get_category_path))
+            .route("{id}/products", web::get().to(list_category_products))
+            .route("{id}/attributes", web::get().to(list_category_attributes))
+            .route("{id}/attributes", web::post().to(add_category_attribute))
+            .route(
+                "{id}/attributes/{name}",
+                web::delete().to(delete_category_attribute),
+            ),
     );
 }
@@ -1,6 +1,12 @@
-use deadpool_postgres::Pool;
+use deadpool_postgres::{Pool, Transaction};
+use std::collections::{BTreeMap, HashMap};
+use tokio_postgres::error::SqlState;
 
-use super::Category;
+use super::{
+    attributes::{AttributeDefinition, AttributeDefinitionInsertable},
+    Category, CategoryDeletion, CategoryInsertable, CategoryMove, CategoryUpdatable,
+};
+use crate::slug;
 
 #[derive(thiserror::Error, Debug)]
 pub enum CategoryStoreError {
@@ -15,6 +21,88 @@ pub enum CategoryStoreError {
 
     #[error("Not Found")]
     NotFound(#[from] anyhow::Error),
+
+    #[error("Parent category not found")]
+    ParentNotFound,
+
+    #[error("Category with this name already exists")]
+    NameTaken,
+
+    #[error("Category with this slug already exists")]
+    SlugTaken,
+
+    #[error("Category has {0} products attached")]
+    HasProducts(i64),
+
+    #[error("Products can't be reassigned to the deleted category or its subcategories")]
+    InvalidTarget,
+
+    #[error("Category can't be moved into itself or its subcategories")]
+    Cycle,
+
+    #[error("Attribute with this name already exists")]
+    AttributeExists,
+
+    #[error("Attribute {0} is defined differently in the categories")]
+    ConflictingAttribute(String),
+}
+
+// build_tree nests categories under their parents in a single pass over the list,
+// categories keep the order they're listed in. Only depth levels are built when it's set.
+fn build_tree(
+    categories: Vec<Category>,
+    root_id: Option<i32>,
+    depth: Option<u32>,
+) -> Vec<Category> {
+    let mut by_parent: HashMap<Option<i32>, Vec<Category>> = HashMap::new();
+
+    for category in categories {
+        by_parent
+            .entry(category.parent_id)
+            .or_default()
+            .push(category);
+    }
+
+    attach_children(&mut by_parent, root_id, depth)
+}
+
+fn attach_children(
+    by_parent: &mut HashMap<Option<i32>, Vec<Category>>,
+    parent_id: Option<i32>,
+    depth: Option<u32>,
+) -> Vec<Category> {
+    if depth == Some(0) {
+        return Vec::new();
+    }
+
+    let mut children = by_parent.remove(&parent_id).unwrap_or_default();
+
+    for child in children.iter_mut() {
+        child.children = attach_children(by_parent, Some(child.id), depth.map(|d| d - 1));
+    }
+
+    children
+}
+
+// unique_violation_or maps violations of the unique sibling name and slug indexes,
+// they can still happen when two requests race past the checks.
+fn unique_violation_or(e: tokio_postgres::Error) -> CategoryStoreError {
+    if e.code() != Some(&SqlState::UNIQUE_VIOLATION) {
+        return e.into();
+    }
+
+    match e.as_db_error().and_then(|e| e.constraint()) {
+        Some("categories_sibling_name") => CategoryStoreError::NameTaken,
+        _ => CategoryStoreError::SlugTaken,
+    }
+}
+
+// join_path appends the slug to the path of the parent category.
+fn join_path(parent_path: Option<&str>, slug: &str) -> String {
+    match parent_path {
+        Some(parent_path) => format!("{}/{}", parent_path, slug),
+        None => slug.to_string(),
+    }
 }
 
 #[derive(Clone)]
@@ -27,37 +115,27 @@ impl CategoryStore {
         Self { db_pool }
     }
 
-    // group_categories groups categories together.
-    // TODO: There is a lot of cloning. Maybe there is a way to avoid it?
-    fn group_categories(&self, id: Option<i32>, categories: Vec<Category>) -> Vec<Category> {
-        let mut parent_categories = categories
-            .iter()
-            .cloned()
-            .filter(|c| c.parent_id == id)
-            .collect::<Vec<Category>>();
-
-        parent_categories.iter_mut().for_each(|c| {
-            let children = self.group_categories(Some(c.id), categories.clone());
-            c.children = children;
-        });
-
-        parent_categories
-    }
-
     pub async fn get_all(&self) -> Result<Vec<Category>, CategoryStoreError> {
         let conn = self.db_pool.get().await?;
 
-        let rows = conn.query("SELECT * FROM categories", &[]).await?;
+        let rows = conn
+            .query("SELECT * FROM categories ORDER BY position, id", &[])
+            .await?;
 
         let all_categories = rows
             .iter()
-            .map(|row| Ok(Category::try_from(row)?))
-            .collect::<Result<Vec<Category>, CategoryStoreError>>()?;
+            .map(Category::try_from)
+            .collect::<Result<Vec<Category>, _>>()?;
 
-        Ok(self.group_categories(None, all_categories))
+        Ok(build_tree(all_categories, None, None))
     }
 
-    pub async fn get_one(&self, id: i32) -> Result<Option<Category>, CategoryStoreError> {
+    // get_one returns the category with its subcategories nested up to depth levels.
+    pub async fn get_one(
+        &self,
+        id: i32,
+        depth: Option<u32>,
+    ) -> Result<Option<Category>, CategoryStoreError> {
         let conn = self.db_pool.get().await?;
 
         let category_row = conn
@@ -69,19 +147,697 @@ impl CategoryStore {
 
             let children_rows = conn
                 .query(
-                    "SELECT c.* FROM categories as c, get_subcategories($1) as sc WHERE c.id IN (sc)",
+                    "SELECT c.* FROM categories AS c WHERE c.id IN (SELECT id FROM get_subcategories($1)) ORDER BY c.position, c.id",
                     &[&id],
                 )
                 .await?;
 
-            category.children = children_rows
+            let subcategories = children_rows
                 .iter()
-                .map(|row| Ok(Category::try_from(row)?))
-                .collect::<Result<Vec<Category>, CategoryStoreError>>()?;
+                .map(Category::try_from)
+                .collect::<Result<Vec<Category>, _>>()?;
+
+            category.children = build_tree(subcategories, Some(id), depth);
 
             Ok(Some(category))
         } else {
             Ok(None)
         }
     }
+
+    // get_by_path returns the category with the given path of slugs, e.g. `electronics/laptops`.
+    pub async fn get_by_path(
+        &self,
+        path: &str,
+        depth: Option<u32>,
+    ) -> Result<Option<Category>, CategoryStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt("SELECT id FROM categories WHERE path = $1", &[&path])
+            .await?;
+
+        match row {
+            Some(row) => self.get_one(row.try_get("id")?, depth).await,
+            None => Ok(None),
+        }
+    }
+
+    // get_path returns categories from the root down to the category,
+    // it's empty if the category doesn't exist.
+    pub async fn get_path(&self, id: i32) -> Result<Vec<Category>, CategoryStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let rows = conn
+            .query(
+                "SELECT c.* FROM categories AS c JOIN get_ancestors($1) AS a ON c.id = a.id ORDER BY a.depth DESC",
+                &[&id],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(Category::try_from)
+            .collect::<Result<Vec<Category>, _>>()?)
+    }
+
+    // is_name_taken checks if a sibling of the category already uses the name (case insensitive).
+    async fn is_name_taken<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        parent_id: Option<i32>,
+        name: &str,
+        exclude_id: Option<i32>,
+    ) -> Result<bool, CategoryStoreError> {
+        let row = transaction
+            .query_one(
+                "SELECT EXISTS (
+                     SELECT 1 FROM categories
+                     WHERE parent_id IS NOT DISTINCT FROM $1 AND lower(name) = lower($2) AND id IS DISTINCT FROM $3
+                 )",
+                &[&parent_id, &name, &exclude_id],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    async fn is_slug_taken<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        parent_id: Option<i32>,
+        slug: &str,
+        exclude_id: Option<i32>,
+    ) -> Result<bool, CategoryStoreError> {
+        let row = transaction
+            .query_one(
+                "SELECT EXISTS (
+                     SELECT 1 FROM categories
+                     WHERE parent_id IS NOT DISTINCT FROM $1 AND slug = $2 AND id IS DISTINCT FROM $3
+                 )",
+                &[&parent_id, &slug, &exclude_id],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    // resolve_slug checks the requested slug, or generates one from the name
+    // with a numeric suffix when a sibling already uses it.
+    async fn resolve_slug<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        parent_id: Option<i32>,
+        requested: Option<&str>,
+        name: &str,
+        exclude_id: Option<i32>,
+    ) -> Result<String, CategoryStoreError> {
+        if let Some(requested) = requested {
+            if self
+                .is_slug_taken(transaction, parent_id, requested, exclude_id)
+                .await?
+            {
+                return Err(CategoryStoreError::SlugTaken);
+            }
+
+            return Ok(requested.to_string());
+        }
+
+        let base = slug::slugify(name, "category");
+        let mut candidate = base.clone();
+        let mut n = 1;
+
+        while self
+            .is_slug_taken(transaction, parent_id, &candidate, exclude_id)
+            .await?
+        {
+            n += 1;
+            candidate = slug::with_suffix(&base, n);
+        }
+
+        Ok(candidate)
+    }
+
+    // get_parent_path returns the path of the parent category, or ParentNotFound.
+    async fn get_parent_path<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        parent_id: Option<i32>,
+    ) -> Result<Option<String>, CategoryStoreError> {
+        match parent_id {
+            Some(parent_id) => {
+                let row = transaction
+                    .query_opt("SELECT path FROM categories WHERE id = $1", &[&parent_id])
+                    .await?
+                    .ok_or(CategoryStoreError::ParentNotFound)?;
+
+                Ok(Some(row.try_get("path")?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // set_path changes the path of the category and the paths of its subcategories.
+    async fn set_path<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        id: i32,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<(), CategoryStoreError> {
+        if old_path == new_path {
+            return Ok(());
+        }
+
+        transaction
+            .execute(
+                "UPDATE categories SET path = $2 WHERE id = $1",
+                &[&id, &new_path],
+            )
+            .await
+            .map_err(unique_violation_or)?;
+
+        transaction
+            .execute(
+                "UPDATE categories SET path = $2 || substr(path, length($3) + 1)
+                 WHERE id IN (SELECT id FROM get_subcategories($1))",
+                &[&id, &new_path, &old_path],
+            )
+            .await
+            .map_err(unique_violation_or)?;
+
+        Ok(())
+    }
+
+    pub async fn insert(
+        &self,
+        category: CategoryInsertable,
+    ) -> Result<Category, CategoryStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let parent_path = self
+                .get_parent_path(&transaction, category.parent_id)
+                .await?;
+
+            if self
+                .is_name_taken(&transaction, category.parent_id, &category.name, None)
+                .await?
+            {
+                return Err(CategoryStoreError::NameTaken);
+            }
+
+            let slug = self
+                .resolve_slug(
+                    &transaction,
+                    category.parent_id,
+                    category.slug.as_deref(),
+                    &category.name,
+                    None,
+                )
+                .await?;
+
+            let path = join_path(parent_path.as_deref(), &slug);
+
+            let row = transaction
+                .query_one(
+                    "INSERT INTO categories (name, parent_id, position, slug, path, meta_title, meta_description)
+                     VALUES ($1, $2, (SELECT COALESCE(MAX(position) + 1, 0) FROM categories WHERE parent_id IS NOT DISTINCT FROM $2), $3, $4, $5, $6)
+                     RETURNING *",
+                    &[
+                        &category.name,
+                        &category.parent_id,
+                        &slug,
+                        &path,
+                        &category.meta_title,
+                        &category.meta_description,
+                    ],
+                )
+                .await
+                .map_err(unique_violation_or)?;
+
+            Ok(Category::try_from(&row)?)
+        }
+        .await;
+
+        if result.is_err() {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+
+        result
+    }
+
+    // update changes the name, slug and metadata of the category, returns None if it doesn't exist.
+    pub async fn update(
+        &self,
+        id: i32,
+        data: CategoryUpdatable,
+    ) -> Result<Option<Category>, CategoryStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            // paths of subcategories are derived from this category, so the tree can't change meanwhile
+            transaction
+                .execute("LOCK TABLE categories IN SHARE ROW EXCLUSIVE MODE", &[])
+                .await?;
+
+            let row = transaction
+                .query_opt("SELECT * FROM categories WHERE id = $1", &[&id])
+                .await?;
+
+            let category = match row {
+                Some(row) => Category::try_from(&row)?,
+                None => return Ok(None),
+            };
+
+            if self
+                .is_name_taken(&transaction, category.parent_id, &data.name, Some(id))
+                .await?
+            {
+                return Err(CategoryStoreError::NameTaken);
+            }
+
+            let slug = match &data.slug {
+                Some(slug) if *slug != category.slug => {
+                    self.resolve_slug(
+                        &transaction,
+                        category.parent_id,
+                        Some(slug),
+                        &data.name,
+                        Some(id),
+                    )
+                    .await?
+                }
+                _ => category.slug.clone(),
+            };
+
+            let parent_path = self
+                .get_parent_path(&transaction, category.parent_id)
+                .await?;
+
+            self.set_path(
+                &transaction,
+                id,
+                &category.path,
+                &join_path(parent_path.as_deref(), &slug),
+            )
+            .await?;
+
+            let row = transaction
+                .query_one(
+                    "UPDATE categories SET name = $1, slug = $2, meta_title = $3, meta_description = $4
+                     WHERE id = $5 RETURNING *",
+                    &[
+                        &data.name,
+                        &slug,
+                        &data.meta_title,
+                        &data.meta_description,
+                        &id,
+                    ],
+                )
+                .await
+                .map_err(unique_violation_or)?;
+
+            Ok(Some(Category::try_from(&row)?))
+        }
+        .await;
+
+        if matches!(result, Ok(Some(_))) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // delete removes the category together with its subcategories, products attached
+    // to any of them are handled according to deletion. Returns false if it doesn't exist.
+    pub async fn delete(
+        &self,
+        id: i32,
+        deletion: CategoryDeletion,
+    ) -> Result<bool, CategoryStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let exists = transaction
+                .query_opt(
+                    "SELECT id FROM categories WHERE id = $1 FOR UPDATE",
+                    &[&id],
+                )
+                .await?
+                .is_some();
+
+            if !exists {
+                return Ok(false);
+            }
+
+            let subtree = "SELECT $1::int UNION SELECT id FROM get_subcategories($1)";
+
+            match deletion {
+                CategoryDeletion::Refuse => {
+                    let products: i64 = transaction
+                        .query_one(
+                            &format!(
+                                "SELECT COUNT(DISTINCT product_id) FROM product_categories WHERE category_id IN ({})",
+                                subtree
+                            ),
+                            &[&id],
+                        )
+                        .await?
+                        .try_get(0)?;
+
+                    if products > 0 {
+                        return Err(CategoryStoreError::HasProducts(products));
+                    }
+                }
+                CategoryDeletion::Reassign(target_id) => {
+                    let valid_target: bool = transaction
+                        .query_one(
+                            &format!(
+                                "SELECT EXISTS (SELECT 1 FROM categories WHERE id = $2 AND id NOT IN ({}))",
+                                subtree
+                            ),
+                            &[&id, &target_id],
+                        )
+                        .await?
+                        .try_get(0)?;
+
+                    if !valid_target {
+                        return Err(CategoryStoreError::InvalidTarget);
+                    }
+
+                    // assignments to the deleted categories are removed by the foreign key cascade
+                    transaction
+                        .execute(
+                            &format!(
+                                "INSERT INTO product_categories (product_id, category_id, position)
+                                 SELECT product_id, $2, MIN(position) FROM product_categories
+                                 WHERE category_id IN ({}) GROUP BY product_id
+                                 ON CONFLICT (product_id, category_id) DO NOTHING",
+                                subtree
+                            ),
+                            &[&id, &target_id],
+                        )
+                        .await?;
+                }
+                CategoryDeletion::Cascade => {
+                    // products in other categories too are only detached,
+                    // asset files of deleted products are removed later by the asset gc
+                    transaction
+                        .execute(
+                            &format!(
+                                "DELETE FROM products WHERE id IN (SELECT product_id FROM product_categories WHERE category_id IN ({0}))
+                                 AND id NOT IN (SELECT product_id FROM product_categories WHERE category_id NOT IN ({0}))",
+                                subtree
+                            ),
+                            &[&id],
+                        )
+                        .await?;
+                }
+            }
+
+            // subcategories are removed by the foreign key cascade
+            transaction
+                .execute("DELETE FROM categories WHERE id = $1", &[&id])
+                .await?;
+
+            // products whose primary category was deleted get a new one,
+            // the reassign target if there's one
+            let target_id = match deletion {
+                CategoryDeletion::Reassign(target_id) => Some(target_id),
+                _ => None,
+            };
+
+            transaction
+                .execute(
+                    "UPDATE product_categories AS pc SET is_primary = TRUE
+                     FROM (
+                         SELECT DISTINCT ON (product_id) product_id, category_id FROM product_categories
+                         WHERE product_id NOT IN (SELECT product_id FROM product_categories WHERE is_primary)
+                         ORDER BY product_id, category_id IS DISTINCT FROM $1, position, category_id
+                     ) AS np
+                     WHERE pc.product_id = np.product_id AND pc.category_id = np.category_id",
+                    &[&target_id],
+                )
+                .await?;
+
+            Ok(true)
+        }
+        .await;
+
+        if matches!(result, Ok(true)) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // set_sibling_positions sets positions of the siblings to their index in ids.
+    async fn set_sibling_positions<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        ids: &[i32],
+    ) -> Result<(), CategoryStoreError> {
+        transaction
+            .execute(
+                "UPDATE categories SET position = o.position - 1
+                 FROM unnest($1::int[]) WITH ORDINALITY AS o(id, position)
+                 WHERE categories.id = o.id",
+                &[&ids],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // move_category moves the category with its subcategories under a new parent
+    // at the given position among siblings. Returns None if it doesn't exist.
+    pub async fn move_category(
+        &self,
+        id: i32,
+        target: CategoryMove,
+    ) -> Result<Option<Category>, CategoryStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            // moves are serialized, otherwise two concurrent moves (e.g. a under b and b under a)
+            // could both pass the cycle check and detach part of the tree
+            transaction
+                .execute("LOCK TABLE categories IN SHARE ROW EXCLUSIVE MODE", &[])
+                .await?;
+
+            let row = transaction
+                .query_opt("SELECT * FROM categories WHERE id = $1", &[&id])
+                .await?;
+
+            let category = match row {
+                Some(row) => Category::try_from(&row)?,
+                None => return Ok(None),
+            };
+
+            let parent_path = self.get_parent_path(&transaction, target.parent_id).await?;
+
+            if let Some(parent_id) = target.parent_id {
+                let is_cycle: bool = transaction
+                    .query_one(
+                        "SELECT $2::int = $1::int OR $2 IN (SELECT id FROM get_subcategories($1))",
+                        &[&id, &parent_id],
+                    )
+                    .await?
+                    .try_get(0)?;
+
+                if is_cycle {
+                    return Err(CategoryStoreError::Cycle);
+                }
+            }
+
+            if self
+                .is_name_taken(&transaction, target.parent_id, &category.name, Some(id))
+                .await?
+            {
+                return Err(CategoryStoreError::NameTaken);
+            }
+
+            if self
+                .is_slug_taken(&transaction, target.parent_id, &category.slug, Some(id))
+                .await?
+            {
+                return Err(CategoryStoreError::SlugTaken);
+            }
+
+            transaction
+                .execute(
+                    "UPDATE categories SET parent_id = $1 WHERE id = $2",
+                    &[&target.parent_id, &id],
+                )
+                .await
+                .map_err(unique_violation_or)?;
+
+            self.set_path(
+                &transaction,
+                id,
+                &category.path,
+                &join_path(parent_path.as_deref(), &category.slug),
+            )
+            .await?;
+
+            // both sibling lists are renumbered, so positions stay contiguous
+            let old_siblings = transaction
+                .query(
+                    "SELECT id FROM categories WHERE parent_id IS NOT DISTINCT FROM $1 AND id <> $2 ORDER BY position, id",
+                    &[&category.parent_id, &id],
+                )
+                .await?
+                .iter()
+                .map(|row| row.try_get(0))
+                .collect::<Result<Vec<i32>, _>>()?;
+
+            self.set_sibling_positions(&transaction, &old_siblings).await?;
+
+            let mut siblings = transaction
+                .query(
+                    "SELECT id FROM categories WHERE parent_id IS NOT DISTINCT FROM $1 AND id <> $2 ORDER BY position, id",
+                    &[&target.parent_id, &id],
+                )
+                .await?
+                .iter()
+                .map(|row| row.try_get(0))
+                .collect::<Result<Vec<i32>, _>>()?;
+
+            let position = target
+                .position
+                .map_or(siblings.len(), |p| (p as usize).min(siblings.len()));
+
+            siblings.insert(position, id);
+            self.set_sibling_positions(&transaction, &siblings).await?;
+
+            let row = transaction
+                .query_one("SELECT * FROM categories WHERE id = $1", &[&id])
+                .await?;
+
+            Ok(Some(Category::try_from(&row)?))
+        }
+        .await;
+
+        if matches!(result, Ok(Some(_))) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // get_attributes returns attribute definitions of the categories, including the ones
+    // inherited from their ancestors. A definition in a subcategory overrides the inherited one.
+    // Every name is defined once, categories defining it with different kinds, options
+    // or units conflict; it's required if any of them requires it.
+    pub async fn get_attributes(
+        &self,
+        category_ids: &[i32],
+    ) -> Result<Vec<AttributeDefinition>, CategoryStoreError> {
+        if category_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let conn = self.db_pool.get().await?;
+
+        let rows = conn
+            .query(
+                "SELECT DISTINCT ON (c.id, d.name) d.*
+                 FROM unnest($1::int[]) AS c(id)
+                 CROSS JOIN LATERAL get_ancestors(c.id) AS a
+                 JOIN category_attributes AS d ON d.category_id = a.id
+                 ORDER BY c.id, d.name, a.depth",
+                &[&category_ids],
+            )
+            .await?;
+
+        let mut definitions: BTreeMap<String, AttributeDefinition> = BTreeMap::new();
+
+        for row in &rows {
+            let definition = AttributeDefinition::try_from(row)?;
+
+            match definitions.get_mut(&definition.name) {
+                Some(d)
+                    if d.kind != definition.kind
+                        || d.options != definition.options
+                        || d.unit != definition.unit =>
+                {
+                    return Err(CategoryStoreError::ConflictingAttribute(definition.name));
+                }
+                Some(d) => d.required |= definition.required,
+                None => {
+                    definitions.insert(definition.name.clone(), definition);
+                }
+            }
+        }
+
+        Ok(definitions.into_values().collect())
+    }
+
+    // add_attribute defines a new attribute in the category, returns None if it doesn't exist.
+    pub async fn add_attribute(
+        &self,
+        category_id: i32,
+        attribute: AttributeDefinitionInsertable,
+    ) -> Result<Option<AttributeDefinition>, CategoryStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt(
+                "INSERT INTO category_attributes (category_id, name, kind, required, options, unit)
+                 SELECT id, $2, $3, $4, $5, $6 FROM categories WHERE id = $1
+                 RETURNING *",
+                &[
+                    &category_id,
+                    &attribute.name,
+                    &attribute.kind.as_str(),
+                    &attribute.required,
+                    &attribute.options,
+                    &attribute.unit,
+                ],
+            )
+            .await
+            .map_err(|e| {
+                if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    CategoryStoreError::AttributeExists
+                } else {
+                    e.into()
+                }
+            })?;
+
+        Ok(row
+            .as_ref()
+            .map(AttributeDefinition::try_from)
+            .transpose()?)
+    }
+
+    // delete_attribute removes the attribute defined in the category, returns false if there's none.
+    pub async fn delete_attribute(
+        &self,
+        category_id: i32,
+        name: &str,
+    ) -> Result<bool, CategoryStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let deleted = conn
+            .execute(
+                "DELETE FROM category_attributes WHERE category_id = $1 AND name = $2",
+                &[&category_id, &name],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
 }
@@ -0,0 +1,132 @@
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    product::{store::ProductStore, AssetFile},
+    storage::Storage,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum GcError {
+    #[error("Storage operation failed")]
+    Storage(#[from] crate::storage::StorageError),
+
+    #[error("Product store operation failed")]
+    ProductStore(#[from] crate::product::store::ProductStoreError),
+}
+
+#[derive(Clone)]
+pub struct GcConfig {
+    // Orphans modified within the grace period are kept, as their assets may still be added.
+    pub grace_period: Duration,
+    // Uploads being completed for longer than completion_timeout are considered stuck, e.g.
+    // after a crash, and are removed once expired.
+    pub completion_timeout: Duration,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    // deleted_orphans contains orphans that were deleted (or would be in dry run mode).
+    pub deleted_orphans: Vec<String>,
+    pub recent_orphans: Vec<String>,
+    // missing_files contains assets whose file doesn't exist in the storage.
+    pub missing_files: Vec<AssetFile>,
+    // expired_uploads contains tokens of direct uploads that were never completed.
+    pub expired_uploads: Vec<String>,
+}
+
+// collect reconciles files in the storage with the assets table.
+pub async fn collect(
+    storage: &Storage,
+    product_store: &ProductStore,
+    config: &GcConfig,
+) -> Result<GcReport, GcError> {
+    let mut report = GcReport {
+        dry_run: config.dry_run,
+        ..Default::default()
+    };
+
+    // files are listed before assets, so a file listed without its asset was either uploaded
+    // before the listing, and is protected by the grace period, or deduplicated into later,
+    // which is caught by delete_orphan
+    let files = storage.list_files().await?;
+    let asset_files = product_store.get_asset_files().await?;
+
+    let referenced = asset_files
+        .iter()
+        .map(|a| a.filename.as_str())
+        .collect::<HashSet<_>>();
+
+    let now = SystemTime::now();
+
+    for file in files {
+        if referenced.contains(file.filename.as_str()) {
+            continue;
+        }
+
+        let age = now.duration_since(file.modified).unwrap_or_default();
+
+        if age < config.grace_period {
+            report.recent_orphans.push(file.filename);
+            continue;
+        }
+
+        // an upload deduplicated into the file since it was listed touches it first,
+        // the file is kept then and its asset is seen by the next run
+        if !config.dry_run && !storage.delete_orphan(&file.filename, file.modified).await? {
+            report.recent_orphans.push(file.filename);
+            continue;
+        }
+
+        report.deleted_orphans.push(file.filename);
+    }
+
+    for asset_file in asset_files {
+        if !storage.file_exists(&asset_file.filename).await? {
+            report.missing_files.push(asset_file);
+        }
+    }
+
+    // expired uploads are only removed outside of dry run, there's nothing to report otherwise
+    if !config.dry_run {
+        for token in product_store
+            .delete_expired_asset_uploads(config.completion_timeout)
+            .await?
+        {
+            storage.delete_upload(&token).await?;
+            report.expired_uploads.push(token);
+        }
+    }
+
+    Ok(report)
+}
+
+// collect_and_log runs collect and logs its outcome, used by the scheduled job.
+pub async fn collect_and_log(storage: &Storage, product_store: &ProductStore, config: &GcConfig) {
+    match collect(storage, product_store, config).await {
+        Ok(report) => {
+            log::info!(
+                "Asset gc finished (dry run: {}): {} orphans deleted, {} recent orphans kept, {} expired uploads removed",
+                report.dry_run,
+                report.deleted_orphans.len(),
+                report.recent_orphans.len(),
+                report.expired_uploads.len()
+            );
+
+            for asset_file in &report.missing_files {
+                log::warn!(
+                    "Asset {} of product {} references missing file {}",
+                    asset_file.asset_id,
+                    asset_file.product_id,
+                    asset_file.filename
+                );
+            }
+        }
+        Err(e) => log::error!("Asset gc failed: {:?}", e),
+    }
+}
@@ -2,23 +2,108 @@ use actix_cors::Cors;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod, Runtime};
 use dotenv::dotenv;
-use product::cache::Cache;
-use std::env;
-use std::sync::{Arc, Mutex};
+use product::cache::{
+    memory::MemoryBackend,
+    redis_backend::{RedisBackend, RedisConnection, RedisJsonBackend, RedisOptions},
+    Cache, CacheBackend, CacheBackendKind, CacheConfig,
+};
+use std::{env, str::FromStr, sync::Arc, time::Duration};
 use tokio_postgres::NoTls;
 
 mod category;
+mod gc;
 mod product;
+mod slug;
 mod storage;
 
-async fn init_redis_connection() -> redis::aio::Connection {
-    let client =
-        redis::Client::open("redis://127.0.0.1:6379/").expect("Invalid redis connection url");
+// PDF manuals and safety data sheets, CAD drawings and models.
+const DEFAULT_DOCUMENT_MIME_TYPES: &str =
+    "application/pdf,image/vnd.dwg,image/vnd.dxf,model/step,model/iges,application/zip";
 
-    client
-        .get_async_connection()
-        .await
-        .expect("Failed to connect with redis")
+// init_redis_connection connects to redis, returning whether it succeeded. When redis is down
+// the server starts anyway and the connection is retried when the cache is used.
+async fn init_redis_connection(options: &RedisOptions) -> (RedisConnection, bool) {
+    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string());
+    let client = redis::Client::open(redis_url).expect("Invalid redis connection url");
+
+    let conn = RedisConnection::new(client, options.clone());
+
+    match conn.connect().await {
+        Ok(_) => (conn, true),
+        Err(e) => {
+            log::warn!(
+                "Failed to connect with redis, serving without cache: {:?}",
+                e
+            );
+            (conn, false)
+        }
+    }
+}
+
+// env_or parses an optional enviroment variable, falling back to default when it's missing.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(v) => v
+            .parse()
+            .unwrap_or_else(|_| panic!("{} enviroment variable is invalid", name)),
+        Err(_) => default,
+    }
+}
+
+// asset_url_ttl is how long signed asset urls are valid in private mode.
+fn asset_url_ttl() -> Duration {
+    Duration::from_secs(env_or("ASSET_URL_TTL_SECS", 60 * 60))
+}
+
+// init_cache connects to redis only when one of the redis backends is selected.
+async fn init_cache() -> Cache {
+    let config = CacheConfig {
+        products_list_ttl: Duration::from_secs(env_or("CACHE_PRODUCTS_LIST_TTL_SECS", 60)),
+        product_ttl: Duration::from_secs(env_or("CACHE_PRODUCT_TTL_SECS", 300)),
+        breaker_threshold: env_or("CACHE_BREAKER_THRESHOLD", 5),
+        breaker_cooldown: Duration::from_secs(env_or("CACHE_BREAKER_COOLDOWN_SECS", 30)),
+        stale_ttl: Duration::from_secs(env_or("CACHE_STALE_TTL_SECS", 30)),
+        lock_ttl: env_or("CACHE_DISTRIBUTED_LOCK", false)
+            .then(|| Duration::from_millis(env_or("CACHE_LOCK_TTL_MS", 5000))),
+    };
+
+    // cached responses embed signed asset urls, they can't outlive the signatures
+    if env::var("ASSET_SIGNING_KEY").is_ok() && config.max_age() >= asset_url_ttl() {
+        panic!(
+            "Cached responses are served for up to {:?}, ASSET_URL_TTL_SECS has to be longer",
+            config.max_age()
+        );
+    }
+
+    let redis_options = RedisOptions {
+        command_timeout: Duration::from_millis(env_or("REDIS_TIMEOUT_MS", 1000)),
+        tag_ttl: config.max_age(),
+    };
+
+    let (backend, connected): (Arc<dyn CacheBackend>, bool) =
+        match env_or("CACHE_BACKEND", CacheBackendKind::Memory) {
+            CacheBackendKind::Memory => (
+                Arc::new(MemoryBackend::new(env_or("CACHE_MEMORY_CAPACITY", 1000))),
+                true,
+            ),
+            CacheBackendKind::Redis => {
+                let (conn, connected) = init_redis_connection(&redis_options).await;
+                (Arc::new(RedisBackend::new(conn)), connected)
+            }
+            CacheBackendKind::RedisJson => {
+                let (conn, connected) = init_redis_connection(&redis_options).await;
+                (Arc::new(RedisJsonBackend::new(conn)), connected)
+            }
+        };
+
+    let cache = Cache::new(backend, config);
+
+    // requests go to the database until the breaker probes redis again
+    if !connected {
+        cache.open_breaker();
+    }
+
+    cache
 }
 
 fn init_db_pool() -> Pool {
@@ -38,6 +123,76 @@ fn init_db_pool() -> Pool {
         .expect("failed to initialize pool")
 }
 
+fn init_storage() -> storage::Storage {
+    // private asset mode is enabled when ASSET_SIGNING_KEY is set
+    let url_signer = env::var("ASSET_SIGNING_KEY")
+        .ok()
+        .map(|key| storage::signing::UrlSigner::new(key.into_bytes(), asset_url_ttl()));
+
+    let document_mime_types = env::var("DOCUMENT_MIME_TYPES")
+        .unwrap_or_else(|_| DEFAULT_DOCUMENT_MIME_TYPES.to_string())
+        .split(',')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect();
+
+    let public_base_url = env::var("PUBLIC_BASE_URL")
+        .unwrap_or_else(|_| "http://127.0.0.1:8080".to_string())
+        .trim_end_matches('/')
+        .to_string();
+
+    let asset_base_url = env::var("ASSET_BASE_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| format!("{}/assets", public_base_url));
+
+    // e.g. `thumbnail={url}?width=200;large=https://cdn.example.com/resize/1200/{filename}`
+    let image_variants = env::var("ASSET_IMAGE_VARIANTS")
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|variant| variant.split_once('='))
+        .map(|(name, template)| (name.trim().to_string(), template.trim().to_string()))
+        .collect();
+
+    storage::Storage::new(storage::StorageConfig {
+        max_files_per_request: env_or("ASSET_MAX_FILES_PER_REQUEST", 10),
+        document_mime_types,
+        max_document_size: env_or("DOCUMENT_MAX_SIZE", 20 * 1024 * 1024),
+        url_signer,
+        public_base_url,
+        asset_base_url,
+        image_variants,
+        upload_ttl: Duration::from_secs(env_or("ASSET_UPLOAD_TTL_SECS", 60 * 60)),
+    })
+}
+
+fn init_gc_config() -> gc::GcConfig {
+    gc::GcConfig {
+        grace_period: Duration::from_secs(env_or("ASSET_GC_GRACE_SECS", 60 * 60)),
+        completion_timeout: Duration::from_secs(env_or(
+            "ASSET_UPLOAD_COMPLETION_TIMEOUT_SECS",
+            10 * 60,
+        )),
+        dry_run: env_or("ASSET_GC_DRY_RUN", false),
+    }
+}
+
+// run_gc_command runs the asset gc once and prints its report, `rustmerce gc [--dry-run]`.
+async fn run_gc_command(args: &[String]) -> std::io::Result<()> {
+    let mut gc_config = init_gc_config();
+    gc_config.dry_run |= args.iter().any(|a| a == "--dry-run");
+
+    let product_store = product::store::ProductStore::new(init_db_pool());
+    let storage_service = init_storage();
+
+    let report = gc::collect(&storage_service, &product_store, &gc_config)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Asset gc failed: {:?}", e)))?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
 fn init_logger() {
     env::set_var("RUST_LOG", "debug");
     env_logger::init();
@@ -48,15 +203,49 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     init_logger();
 
-    std::fs::create_dir_all("./assets").expect("Failed to create ./assets");
+    std::fs::create_dir_all(storage::TMP_DIR).expect("Failed to create assets directory");
+    std::fs::create_dir_all(storage::UPLOADS_DIR).expect("Failed to create uploads directory");
+
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    if args.first().map(String::as_str) == Some("gc") {
+        return run_gc_command(&args[1..]).await;
+    }
 
     let db_pool = init_db_pool();
 
     let product_store = product::store::ProductStore::new(db_pool.clone());
     let category_store = category::store::CategoryStore::new(db_pool.clone());
 
-    let cache = Cache::new(init_redis_connection().await);
-    let storage_service = storage::Storage::new();
+    let cache = init_cache().await;
+    let storage_service = init_storage();
+
+    let removed_tmp_files = storage_service
+        .clean_tmp_files()
+        .await
+        .expect("Failed to clean temporary asset files");
+
+    if removed_tmp_files > 0 {
+        log::info!("Removed {} incomplete uploads", removed_tmp_files);
+    }
+
+    // periodically remove orphaned asset files, 0 disables the job
+    let gc_interval = env_or("ASSET_GC_INTERVAL_SECS", 60 * 60);
+
+    if gc_interval > 0 {
+        let gc_config = init_gc_config();
+        let storage_service = storage_service.clone();
+        let product_store = product_store.clone();
+
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(Duration::from_secs(gc_interval));
+
+            loop {
+                interval.tick().await;
+                gc::collect_and_log(&storage_service, &product_store, &gc_config).await;
+            }
+        });
+    }
 
     HttpServer::new(move || {
         let logger = Logger::default();
@@ -73,8 +262,9 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(storage_service.clone()))
             .app_data(web::Data::new(category_store.clone()))
             .app_data(web::Data::new(cache.clone()))
-            .service(actix_files::Files::new("/assets", "./assets").show_files_listing())
+            .configure(storage::handlers::config)
             .configure(product::handlers::config)
+            .configure(product::cache::handlers::config)
             .configure(category::handlers::config)
     })
     .bind(("127.0.0.1", 8080))?
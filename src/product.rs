@@ -1,19 +1,197 @@
 use serde::{Deserialize, Serialize};
-use tokio_pg_mapper_derive::PostgresMapper;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tokio_postgres::Row;
 use validator::Validate;
 
+use crate::{category::Category, storage::FileKind};
+
 pub mod cache;
+pub mod filters;
 pub mod handlers;
 pub mod store;
 
-#[derive(Serialize, Deserialize, PostgresMapper)]
-#[pg_mapper(table = "assets")]
+#[derive(Serialize, Deserialize)]
 pub struct Asset {
     pub id: i32,
+    pub product_id: i32,
+    pub filename: String,
+    pub hash: String,
+    pub kind: FileKind,
+    pub original_filename: Option<String>,
+    pub position: i32,
+    pub is_primary: bool,
+    pub alt_text: Option<String>,
+    // url and variants are resolved by the handlers before the asset is returned.
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub variants: BTreeMap<String, String>,
+}
+
+impl TryFrom<&Row> for Asset {
+    type Error = tokio_pg_mapper::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let kind: &str = row.try_get("kind")?;
+
+        Ok(Asset {
+            id: row.try_get("id")?,
+            product_id: row.try_get("product_id")?,
+            filename: row.try_get("filename")?,
+            hash: row.try_get("hash")?,
+            kind: {
+                match kind {
+                    "document" => FileKind::Document,
+                    _ => FileKind::Image,
+                }
+            },
+            original_filename: row.try_get("original_filename")?,
+            position: row.try_get("position")?,
+            is_primary: row.try_get("is_primary")?,
+            alt_text: row.try_get("alt_text")?,
+            url: String::new(),
+            variants: BTreeMap::new(),
+        })
+    }
+}
+
+// AssetFile links an asset to the file it references.
+#[derive(Debug, Serialize)]
+pub struct AssetFile {
+    pub asset_id: i32,
+    pub product_id: i32,
     pub filename: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct AssetUpdatable {
+    #[validate(length(max = 500))]
+    pub alt_text: Option<String>,
+
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssetByHash {
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssetOrder {
+    pub asset_ids: Vec<i32>,
+}
+
+// AssetUploadInsertable declares a file that will be sent with a direct upload.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct AssetUploadInsertable {
+    #[validate(length(min = 1))]
+    pub content_type: String,
+
+    pub filename: Option<String>,
+
+    #[validate(range(min = 1))]
+    pub size: i64,
+}
+
+#[derive(Debug)]
+pub enum AssetUploadStatus {
+    Pending,
+    Uploading,
+    Uploaded,
+    Completing,
+}
+
+impl AssetUploadStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Uploading => "uploading",
+            Self::Uploaded => "uploaded",
+            Self::Completing => "completing",
+        }
+    }
+}
+
+// AssetUpload is a direct upload, its token is the only credential needed to send the file.
+#[derive(Debug)]
+pub struct AssetUpload {
+    pub token: String,
+    pub content_type: String,
+    pub original_filename: Option<String>,
+    pub size: i64,
+    // expires_at is a unix timestamp in seconds.
+    pub expires_at: u64,
+}
+
+impl TryFrom<&Row> for AssetUpload {
+    type Error = tokio_pg_mapper::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let expires_at: SystemTime = row.try_get("expires_at")?;
+
+        Ok(AssetUpload {
+            token: row.try_get("token")?,
+            content_type: row.try_get("content_type")?,
+            original_filename: row.try_get("original_filename")?,
+            size: row.try_get("size")?,
+            expires_at: expires_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+}
+
+// AssetUploadResult describes the outcome of a single file in a batch upload.
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum AssetUploadResult {
+    Created {
+        filename: Option<String>,
+        asset: Asset,
+    },
+    Failed {
+        filename: Option<String>,
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ProductCategory {
+    pub category_id: i32,
+
+    #[serde(default)]
+    pub is_primary: bool,
+
+    // position is the sort position of the product in the category listing.
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    pub position: i32,
+}
+
+impl TryFrom<&Row> for ProductCategory {
+    type Error = tokio_pg_mapper::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(ProductCategory {
+            category_id: row.try_get("category_id")?,
+            is_primary: row.try_get("is_primary")?,
+            position: row.try_get("position")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ProductCategories {
+    #[validate]
+    pub categories: Vec<ProductCategory>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum ProductStatus {
     Published,
@@ -26,7 +204,15 @@ pub struct Product {
     pub name: String,
     pub price: f64,
     pub status: ProductStatus,
+    pub slug: String,
+    // category_id is the primary category of the product.
+    pub category_id: Option<i32>,
+    pub categories: Vec<ProductCategory>,
+    pub attributes: Value,
     pub assets: Vec<Asset>,
+    // breadcrumbs are categories from the root down to the product's category, see `?expand=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breadcrumbs: Option<Vec<Category>>,
 }
 
 impl TryFrom<&Row> for Product {
@@ -45,11 +231,54 @@ impl TryFrom<&Row> for Product {
                     _ => ProductStatus::Published,
                 }
             },
+            slug: row.try_get("slug")?,
+            category_id: None,
+            categories: Vec::new(),
+            attributes: row.try_get("attributes")?,
             assets: Vec::new(),
+            breadcrumbs: None,
         })
     }
 }
 
+// ProductList is the products listing with facets, returned when `?facets=` is given.
+#[derive(Serialize, Deserialize)]
+pub struct ProductList {
+    pub products: Vec<Product>,
+    pub facets: BTreeMap<String, FacetCounts>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FacetCounts {
+    Price(Vec<PriceRangeCount>),
+    Categories(Vec<CategoryCount>),
+    Values(Vec<ValueCount>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceRangeCount {
+    pub min: f64,
+    // max is exclusive, the last range is open.
+    pub max: Option<f64>,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CategoryCount {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    // count includes products in subcategories.
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ValueCount {
+    pub value: Value,
+    pub count: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct ProductInsertable {
     #[validate(length(min = 1))]
@@ -57,6 +286,26 @@ pub struct ProductInsertable {
 
     #[validate(range(min = 1))]
     pub price: f64,
+
+    // slug is generated from the name when it's missing.
+    #[validate(custom = "crate::slug::validate_slug")]
+    pub slug: Option<String>,
+
+    // attributes are validated against attribute definitions of the product's categories.
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+
+    // categories replace the product's categories when they're given.
+    #[validate]
+    pub categories: Option<Vec<ProductCategory>>,
+}
+
+// ProductBySlug is the outcome of looking a product up by slug.
+pub enum ProductBySlug {
+    Found(Product),
+    // Moved means the slug belonged to the product before it was renamed.
+    Moved(String),
+    NotFound,
 }
 
 // #[derive(Message)]
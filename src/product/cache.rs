@@ -1,8 +1,37 @@
+use async_trait::async_trait;
+use breaker::{BreakerState, CircuitBreaker};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    ops::DerefMut,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+pub mod breaker;
+pub mod handlers;
+pub mod memory;
+pub mod redis_backend;
+
+// PRODUCTS_LIST_TAG tags every cached products listing, any product write changes them.
+pub const PRODUCTS_LIST_TAG: &str = "products:list";
+
+// CATEGORIES_TAG tags cached products, category writes change their categories and breadcrumbs.
+pub const CATEGORIES_TAG: &str = "categories";
+
+// product_tag tags cached responses which include the product.
+pub fn product_tag(product_id: i32) -> String {
+    format!("product:{}", product_id)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum CacheError {
     #[error("Serialization Failed")]
@@ -10,44 +39,447 @@ pub enum CacheError {
 
     #[error("Redis operation failed")]
     Redis(#[from] redis::RedisError),
+
+    #[error("Redis operation timed out")]
+    Timeout,
+}
+
+// CacheBackend stores serialized responses, entries are deleted when any of their tags
+// is invalidated.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError>;
+
+    // generations returns the generation of each tag, invalidating a tag bumps it.
+    async fn generations(&self, tags: &[&str]) -> Result<Vec<u64>, CacheError>;
+
+    // set stores the entry only if its tags are still at the given generations,
+    // so data loaded before an invalidation isn't cached after it.
+    async fn set(
+        &self,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) -> Result<(), CacheError>;
+
+    async fn invalidate(&self, tags: &[&str]) -> Result<(), CacheError>;
+
+    // lock takes the lock for loading the key across processes and returns its token,
+    // or None when another process holds it. Backends local to the process don't need it.
+    async fn lock(&self, _key: &str, _ttl: Duration) -> Result<Option<String>, CacheError> {
+        Ok(Some(String::new()))
+    }
+
+    async fn unlock(&self, _key: &str, _token: &str) -> Result<(), CacheError> {
+        Ok(())
+    }
+}
+
+// CacheBackendKind selects the backend with CACHE_BACKEND.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheBackendKind {
+    // Memory keeps entries in the process, so it doesn't need redis.
+    Memory,
+    // Redis stores entries as plain strings.
+    Redis,
+    // RedisJson stores entries as JSON documents, it requires the RedisJSON module.
+    RedisJson,
+}
+
+impl FromStr for CacheBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "redis" => Ok(Self::Redis),
+            "redisjson" => Ok(Self::RedisJson),
+            _ => Err(format!("Unknown cache backend {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub products_list_ttl: Duration,
+    pub product_ttl: Duration,
+    // breaker_threshold is the number of consecutive failures opening the circuit breaker.
+    pub breaker_threshold: u32,
+    // breaker_cooldown is how long the backend is skipped before it's probed again.
+    pub breaker_cooldown: Duration,
+    // stale_ttl is how long an expired entry is still served while it's being refreshed.
+    pub stale_ttl: Duration,
+    // lock_ttl enables the distributed lock, so a single process loads an expired entry.
+    pub lock_ttl: Option<Duration>,
 }
 
+impl CacheConfig {
+    // max_age is the longest an entry is served for, including while it's stale.
+    pub fn max_age(&self) -> Duration {
+        self.products_list_ttl.max(self.product_ttl) + self.stale_ttl
+    }
+}
+
+// How often a process waiting for the distributed lock checks if the entry was stored.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// CachedEntry is what's stored in the backend, fresh_until is in unix milliseconds.
+// The backend keeps it for stale_ttl longer, so it can be served while it's refreshed.
+#[derive(Serialize, Deserialize)]
+struct CachedEntry {
+    fresh_until: u64,
+    data: String,
+}
+
+enum Lookup {
+    Fresh(String),
+    Stale(String),
+    Miss,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Load is a load of a key shared by all requests waiting for it.
+type Load = Shared<BoxFuture<'static, Result<Option<String>, Arc<anyhow::Error>>>>;
+
+// InFlight is a load in progress, id tells it apart from later loads of the same key.
+struct InFlight {
+    id: u64,
+    tags: Vec<String>,
+    load: Load,
+}
+
+enum LockWait {
+    // Fresh is the entry stored by the process holding the lock.
+    Fresh(String),
+    // Acquired is the token of the lock, released without storing the entry.
+    Acquired(String),
+    TimedOut,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    get_errors: AtomicU64,
+    set_errors: AtomicU64,
+    invalidate_errors: AtomicU64,
+    lock_errors: AtomicU64,
+    // skipped are operations not sent to the backend while the breaker was open.
+    skipped: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub breaker: BreakerState,
+    pub get_errors: u64,
+    pub set_errors: u64,
+    pub invalidate_errors: u64,
+    pub lock_errors: u64,
+    pub skipped: u64,
+}
+
+// Cache never fails requests, backend errors are logged and counted and the caller
+// falls through to the database. A circuit breaker stops calling a backend that's down.
 #[derive(Clone)]
 pub struct Cache {
-    pub redis_conn: Arc<Mutex<redis::aio::Connection>>,
+    backend: Arc<dyn CacheBackend>,
+    config: CacheConfig,
+    breaker: Arc<CircuitBreaker>,
+    counters: Arc<CacheCounters>,
+    // loads are the keys being loaded by this process, concurrent requests share the load.
+    loads: Arc<Mutex<HashMap<String, InFlight>>>,
+    next_load_id: Arc<AtomicU64>,
 }
 
 impl Cache {
-    pub fn new(redis_conn: redis::aio::Connection) -> Self {
+    pub fn new(backend: Arc<dyn CacheBackend>, config: CacheConfig) -> Self {
+        let breaker = CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown);
+
         Self {
-            redis_conn: Arc::new(Mutex::new(redis_conn)),
+            backend,
+            config,
+            breaker: Arc::new(breaker),
+            counters: Arc::default(),
+            loads: Arc::default(),
+            next_load_id: Arc::default(),
         }
     }
 
-    pub async fn set(&self, endpoint: &str, data: &str) -> Result<(), CacheError> {
-        let mut redis_conn = self.redis_conn.lock().unwrap();
+    // open_breaker skips the backend until the breaker's cooldown is over.
+    pub fn open_breaker(&self) {
+        self.breaker.open();
+    }
 
-        redis::pipe()
-            .cmd("JSON.SET")
-            .arg(&[endpoint, "$", data])
-            .ignore()
-            .cmd("expire")
-            .arg(&[endpoint, "5"])
-            .ignore()
-            .query_async(redis_conn.deref_mut())
-            .await?;
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            breaker: self.breaker.state(),
+            get_errors: self.counters.get_errors.load(Ordering::Relaxed),
+            set_errors: self.counters.set_errors.load(Ordering::Relaxed),
+            invalidate_errors: self.counters.invalidate_errors.load(Ordering::Relaxed),
+            lock_errors: self.counters.lock_errors.load(Ordering::Relaxed),
+            skipped: self.counters.skipped.load(Ordering::Relaxed),
+        }
+    }
 
-        Ok(())
+    // guarded runs the operation unless the breaker is open, failures are logged and
+    // counted in errors.
+    async fn guarded<T>(
+        &self,
+        operation: &str,
+        errors: &AtomicU64,
+        f: impl Future<Output = Result<T, CacheError>>,
+    ) -> Option<T> {
+        if !self.breaker.allow() {
+            self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        match f.await {
+            Ok(v) => {
+                self.breaker.record_success();
+                Some(v)
+            }
+            Err(e) => {
+                errors.fetch_add(1, Ordering::Relaxed);
+                self.breaker.record_failure();
+                log::error!("Cache {} failed: {:?}", operation, e);
+                None
+            }
+        }
+    }
+
+    // products_list_ttl is how long `/products` responses are cached.
+    pub fn products_list_ttl(&self) -> Duration {
+        self.config.products_list_ttl
+    }
+
+    // product_ttl is how long `/products/{id}` responses are cached.
+    pub fn product_ttl(&self) -> Duration {
+        self.config.product_ttl
+    }
+
+    // get_or_load returns the cached data, or loads and caches it. Concurrent requests
+    // for a key share a single load, and an expired entry is served for stale_ttl while
+    // it's refreshed in the background. Data loaded as None (e.g. not found) isn't cached.
+    pub async fn get_or_load<F, Fut>(
+        &self,
+        key: &str,
+        tags: &[&str],
+        ttl: Duration,
+        load: F,
+    ) -> anyhow::Result<Option<String>>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<Option<String>>> + Send + 'static,
+    {
+        match self.lookup(key).await {
+            Lookup::Fresh(data) => Ok(Some(data)),
+            Lookup::Stale(data) => {
+                let refresh = self.load_once(key, tags, ttl, load);
+                let key = key.to_string();
+
+                tokio::spawn(async move {
+                    if let Err(e) = refresh.await {
+                        log::error!("Failed to refresh cached {}: {:?}", key, e);
+                    }
+                });
+
+                Ok(Some(data))
+            }
+            Lookup::Miss => self
+                .load_once(key, tags, ttl, load)
+                .await
+                .map_err(|e| Arc::try_unwrap(e).unwrap_or_else(|e| anyhow::anyhow!("{:#}", e))),
+        }
+    }
+
+    async fn lookup(&self, key: &str) -> Lookup {
+        let get = self.backend.get(key);
+        let raw = match self.guarded("get", &self.counters.get_errors, get).await {
+            Some(Some(raw)) => raw,
+            _ => return Lookup::Miss,
+        };
+
+        match serde_json::from_str::<CachedEntry>(&raw) {
+            Ok(entry) if entry.fresh_until > now_millis() => Lookup::Fresh(entry.data),
+            Ok(entry) => Lookup::Stale(entry.data),
+            Err(e) => {
+                self.counters.get_errors.fetch_add(1, Ordering::Relaxed);
+                log::error!("Cached {} is malformed: {:?}", key, e);
+                Lookup::Miss
+            }
+        }
+    }
+
+    // load_once joins the load of the key in progress, or starts it.
+    fn load_once<F, Fut>(&self, key: &str, tags: &[&str], ttl: Duration, load: F) -> Load
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<Option<String>>> + Send + 'static,
+    {
+        let mut loads = self.loads.lock().unwrap();
+
+        if let Some(in_flight) = loads.get(key) {
+            return in_flight.load.clone();
+        }
+
+        let cache = self.clone();
+        let key = key.to_string();
+        let tags = tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let id = self.next_load_id.fetch_add(1, Ordering::Relaxed);
+
+        let shared = {
+            let key = key.clone();
+            let tags = tags.clone();
+
+            async move {
+                let result = cache.load_locked(&key, &tags, ttl, load).await;
+
+                // an invalidation may have replaced the load with a newer one already
+                let mut loads = cache.loads.lock().unwrap();
+                if loads.get(&key).is_some_and(|l| l.id == id) {
+                    loads.remove(&key);
+                }
+
+                result.map_err(Arc::new)
+            }
+            .boxed()
+            .shared()
+        };
+
+        loads.insert(
+            key,
+            InFlight {
+                id,
+                tags,
+                load: shared.clone(),
+            },
+        );
+
+        shared
     }
 
-    pub async fn get(&self, endpoint: &str) -> Result<Option<String>, CacheError> {
-        let mut redis_conn = self.redis_conn.lock().unwrap();
+    // load_locked loads and stores the entry, holding the distributed lock when it's enabled.
+    // Without the lock it waits for the process holding it to store the entry, or to release
+    // the lock without storing it (e.g. not found), and loads it by itself if neither happens
+    // in time.
+    async fn load_locked<F, Fut>(
+        &self,
+        key: &str,
+        tags: &[String],
+        ttl: Duration,
+        load: F,
+    ) -> anyhow::Result<Option<String>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<String>>>,
+    {
+        let mut token = None;
+
+        if let Some(lock_ttl) = self.config.lock_ttl {
+            let lock = self.backend.lock(key, lock_ttl);
+
+            match self.guarded("lock", &self.counters.lock_errors, lock).await {
+                Some(Some(t)) => token = Some(t),
+                Some(None) => match self.wait_for_lock(key, lock_ttl).await {
+                    LockWait::Fresh(data) => return Ok(Some(data)),
+                    LockWait::Acquired(t) => token = Some(t),
+                    LockWait::TimedOut => {}
+                },
+                // the backend is unavailable, so the lock isn't needed
+                None => {}
+            }
+        }
+
+        let tags = tags.iter().map(String::as_str).collect::<Vec<_>>();
+
+        // generations are read before loading, an invalidation during the load bumps them
+        let generations = self.backend.generations(&tags);
+        let generations = self
+            .guarded("generations", &self.counters.get_errors, generations)
+            .await;
+
+        let result = load().await;
+
+        if let (Ok(Some(data)), Some(generations)) = (&result, generations) {
+            self.store(key, data, &tags, &generations, ttl).await;
+        }
+
+        if let Some(token) = token {
+            let unlock = self.backend.unlock(key, &token);
+            self.guarded("unlock", &self.counters.lock_errors, unlock)
+                .await;
+        }
+
+        result
+    }
+
+    // wait_for_lock waits until the entry is stored or the lock is released, taking it then.
+    async fn wait_for_lock(&self, key: &str, timeout: Duration) -> LockWait {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+
+            if let Lookup::Fresh(data) = self.lookup(key).await {
+                return LockWait::Fresh(data);
+            }
+
+            let lock = self.backend.lock(key, timeout);
+
+            match self.guarded("lock", &self.counters.lock_errors, lock).await {
+                Some(Some(token)) => return LockWait::Acquired(token),
+                Some(None) => {}
+                // the backend is unavailable, so the lock isn't needed
+                None => return LockWait::TimedOut,
+            }
+        }
+
+        LockWait::TimedOut
+    }
+
+    // store caches the data for ttl, invalidating any of the tags deletes it.
+    // It's skipped if any of the tags was invalidated since the generations were read.
+    async fn store(
+        &self,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) {
+        let entry = CachedEntry {
+            fresh_until: now_millis() + ttl.as_millis() as u64,
+            data: data.to_string(),
+        };
+
+        let set = async {
+            let entry = serde_json::to_string(&entry)?;
+            self.backend
+                .set(key, &entry, tags, generations, ttl + self.config.stale_ttl)
+                .await
+        };
+
+        self.guarded("set", &self.counters.set_errors, set).await;
+    }
 
-        let serialized = redis::cmd("JSON.GET")
-            .arg(endpoint)
-            .query_async::<_, Option<String>>(redis_conn.deref_mut())
-            .await?;
+    // invalidate deletes all entries tagged with any of the tags. When it fails, entries
+    // stay stale until they expire. Loads of tagged keys in progress may have read the data
+    // before the change, so later requests don't join them and start new loads.
+    pub async fn invalidate(&self, tags: &[&str]) {
+        let invalidate = self.backend.invalidate(tags);
+        self.guarded("invalidate", &self.counters.invalidate_errors, invalidate)
+            .await;
 
-        Ok(serialized)
+        self.loads
+            .lock()
+            .unwrap()
+            .retain(|_, l| !l.tags.iter().any(|t| tags.contains(&t.as_str())));
     }
 }
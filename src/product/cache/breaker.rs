@@ -0,0 +1,110 @@
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    // Open skips the backend until the cooldown is over.
+    Open,
+    // HalfOpen lets a single probe through, its outcome closes or reopens the breaker.
+    HalfOpen,
+}
+
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+// CircuitBreaker stops calling a failing backend after threshold consecutive failures,
+// and probes it again every cooldown until it recovers. opened_at is when the breaker
+// opened or the last probe started.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    breaker: Mutex<Breaker>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+            }),
+        }
+    }
+
+    // open skips the backend until the cooldown is over, e.g. when it's down at startup.
+    pub fn open(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.state = BreakerState::Open;
+        breaker.opened_at = Some(Instant::now());
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.breaker.lock().unwrap().state
+    }
+
+    // allow tells if the backend can be called, after the cooldown it lets the probe through.
+    pub fn allow(&self) -> bool {
+        let mut breaker = self.breaker.lock().unwrap();
+
+        if breaker.state == BreakerState::Closed {
+            return true;
+        }
+
+        // a probe is in flight otherwise, it's replaced if it never finished (e.g. was cancelled)
+        if breaker
+            .opened_at
+            .is_some_and(|t| t.elapsed() >= self.cooldown)
+        {
+            breaker.state = BreakerState::HalfOpen;
+            breaker.opened_at = Some(Instant::now());
+            return true;
+        }
+
+        false
+    }
+
+    pub fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+
+        if breaker.state != BreakerState::Closed {
+            log::info!("Cache backend recovered, closing the circuit breaker");
+        }
+
+        breaker.state = BreakerState::Closed;
+        breaker.consecutive_failures = 0;
+        breaker.opened_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.consecutive_failures += 1;
+
+        let open = match breaker.state {
+            BreakerState::HalfOpen => true,
+            BreakerState::Closed => breaker.consecutive_failures >= self.threshold,
+            BreakerState::Open => false,
+        };
+
+        if open {
+            log::warn!(
+                "Cache backend failed {} times in a row, skipping it for {:?}",
+                breaker.consecutive_failures,
+                self.cooldown
+            );
+
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use actix_web::{web, HttpResponse};
+
+use super::Cache;
+
+// get_cache_stats reports cache errors and the state of the circuit breaker.
+async fn get_cache_stats(cache: web::Data<Cache>) -> HttpResponse {
+    HttpResponse::Ok().json(cache.stats())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/cache/stats", web::get().to(get_cache_stats));
+}
@@ -0,0 +1,171 @@
+use super::{CacheBackend, CacheError};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Entry {
+    data: String,
+    expires_at: Instant,
+    tags: Vec<String>,
+    // used is the tick of the last access, the key in `recency`.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    // recency orders keys from the least recently used.
+    recency: BTreeMap<u64, String>,
+    tags: HashMap<String, HashSet<String>>,
+    // generations of invalidated tags, other tags are at 0.
+    generations: HashMap<String, u64>,
+    tick: u64,
+}
+
+impl Entries {
+    fn generation(&self, tag: &str) -> u64 {
+        self.generations.get(tag).copied().unwrap_or_default()
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &str) {
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        self.recency.remove(&entry.used);
+
+        for tag in entry.tags {
+            if let Some(keys) = self.tags.get_mut(&tag) {
+                keys.remove(key);
+
+                if keys.is_empty() {
+                    self.tags.remove(&tag);
+                }
+            }
+        }
+    }
+}
+
+// MemoryBackend keeps entries in the process, the least recently used ones are evicted
+// when there are more than capacity of them. Expired entries are removed on access.
+pub struct MemoryBackend {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl MemoryBackend {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        let mut entries = self.entries.lock().unwrap();
+        let tick = entries.next_tick();
+
+        let (data, used) = match entries.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                (entry.data.clone(), std::mem::replace(&mut entry.used, tick))
+            }
+            Some(_) => {
+                entries.remove(key);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+
+        entries.recency.remove(&used);
+        entries.recency.insert(tick, key.to_string());
+
+        Ok(Some(data))
+    }
+
+    async fn generations(&self, tags: &[&str]) -> Result<Vec<u64>, CacheError> {
+        let entries = self.entries.lock().unwrap();
+
+        Ok(tags.iter().map(|tag| entries.generation(tag)).collect())
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) -> Result<(), CacheError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if tags
+            .iter()
+            .zip(generations)
+            .any(|(tag, generation)| entries.generation(tag) != *generation)
+        {
+            return Ok(());
+        }
+
+        entries.remove(key);
+
+        let used = entries.next_tick();
+
+        for tag in tags {
+            entries
+                .tags
+                .entry(tag.to_string())
+                .or_default()
+                .insert(key.to_string());
+        }
+
+        entries.recency.insert(used, key.to_string());
+        entries.entries.insert(
+            key.to_string(),
+            Entry {
+                data: data.to_string(),
+                expires_at: Instant::now() + ttl,
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                used,
+            },
+        );
+
+        while entries.entries.len() > self.capacity {
+            let oldest = match entries.recency.first_key_value() {
+                Some((_, key)) => key.clone(),
+                None => break,
+            };
+
+            entries.remove(&oldest);
+        }
+
+        Ok(())
+    }
+
+    async fn invalidate(&self, tags: &[&str]) -> Result<(), CacheError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        for tag in tags {
+            *entries.generations.entry(tag.to_string()).or_default() += 1;
+
+            if let Some(keys) = entries.tags.remove(*tag) {
+                for key in keys {
+                    entries.remove(&key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
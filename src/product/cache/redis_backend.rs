@@ -0,0 +1,337 @@
+use super::{CacheBackend, CacheError};
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, RedisResult};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// tag_key is the redis set with keys of the entries tagged with the tag.
+fn tag_key(tag: &str) -> String {
+    format!("tag:{}", tag)
+}
+
+// generation_key is the counter of the tag's invalidations, a missing one is at 0.
+// Counters never expire, a counter going back to 0 would let stale entries be stored.
+fn generation_key(tag: &str) -> String {
+    format!("gen:{}", tag)
+}
+
+// SET_SCRIPT stores the entry and adds its key to the tag sets, unless a tag was invalidated
+// since its generation was read. KEYS are the entry key, then the tag sets, then the tags'
+// generation counters. ARGV are `json` or `string`, the value, its ttl, the tag sets' ttl
+// and the generations.
+const SET_SCRIPT: &str = r"
+local n = (#KEYS - 1) / 2
+for i = 1, n do
+    if (redis.call('GET', KEYS[1 + n + i]) or '0') ~= ARGV[4 + i] then
+        return 0
+    end
+end
+if ARGV[1] == 'json' then
+    redis.call('JSON.SET', KEYS[1], '$', ARGV[2])
+    redis.call('EXPIRE', KEYS[1], ARGV[3])
+else
+    redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+end
+for i = 1, n do
+    redis.call('SADD', KEYS[1 + i], KEYS[1])
+    redis.call('EXPIRE', KEYS[1 + i], ARGV[4])
+end
+return 1
+";
+
+// INVALIDATE_SCRIPT deletes entries in the tag sets and the sets themselves, and bumps the
+// tags' generations. KEYS are the tag sets followed by the generation counters.
+const INVALIDATE_SCRIPT: &str = r"
+local n = #KEYS / 2
+for i = 1, n do
+    for _, key in ipairs(redis.call('SMEMBERS', KEYS[i])) do
+        redis.call('DEL', key)
+    end
+    redis.call('DEL', KEYS[i])
+    redis.call('INCR', KEYS[n + i])
+end
+";
+
+// lock_key is the key of the distributed lock for loading the entry.
+fn lock_key(key: &str) -> String {
+    format!("lock:{}", key)
+}
+
+// UNLOCK_SCRIPT releases the lock only if it's still held with the token,
+// it could have expired and been taken by another process.
+const UNLOCK_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+#[derive(Debug, Clone)]
+pub struct RedisOptions {
+    // command_timeout bounds every redis command, including reconnecting.
+    pub command_timeout: Duration,
+    // tag_ttl has to outlive every entry, so a tag set never expires before the entries it lists.
+    pub tag_ttl: Duration,
+}
+
+// RedisConnection is shared by both redis backends, they differ only in how values are stored.
+// The connection is multiplexed, it's cloned for every command instead of being locked,
+// and reconnects by itself after the connection is lost. It's established on first use
+// if redis was down when the connection was created.
+#[derive(Clone)]
+pub struct RedisConnection {
+    client: redis::Client,
+    conn: Arc<Mutex<Option<ConnectionManager>>>,
+    options: RedisOptions,
+}
+
+impl RedisConnection {
+    pub fn new(client: redis::Client, options: RedisOptions) -> Self {
+        Self {
+            client,
+            conn: Arc::default(),
+            options,
+        }
+    }
+
+    // connect establishes the connection, bounded by the command timeout.
+    pub async fn connect(&self) -> Result<ConnectionManager, CacheError> {
+        let conn = self
+            .with_timeout(self.client.get_tokio_connection_manager())
+            .await?;
+
+        *self.conn.lock().unwrap() = Some(conn.clone());
+
+        Ok(conn)
+    }
+
+    // manager returns the connection, connecting first if it isn't established yet.
+    async fn manager(&self) -> Result<ConnectionManager, CacheError> {
+        let conn = self.conn.lock().unwrap().clone();
+
+        match conn {
+            Some(conn) => Ok(conn),
+            None => self.connect().await,
+        }
+    }
+
+    async fn with_timeout<T>(
+        &self,
+        command: impl Future<Output = RedisResult<T>>,
+    ) -> Result<T, CacheError> {
+        match tokio::time::timeout(self.options.command_timeout, command).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(CacheError::Timeout),
+        }
+    }
+
+    fn ttl_secs(ttl: Duration) -> u64 {
+        ttl.as_secs().max(1)
+    }
+
+    // set stores the entry as a string or, with json, as a JSON document.
+    async fn set(
+        &self,
+        json: bool,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) -> Result<(), CacheError> {
+        let mut conn = self.manager().await?;
+
+        let script = redis::Script::new(SET_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(key);
+
+        for tag in tags {
+            invocation.key(tag_key(tag));
+        }
+
+        for tag in tags {
+            invocation.key(generation_key(tag));
+        }
+
+        invocation
+            .arg(if json { "json" } else { "string" })
+            .arg(data)
+            .arg(Self::ttl_secs(ttl))
+            .arg(Self::ttl_secs(self.options.tag_ttl));
+
+        for generation in generations {
+            invocation.arg(generation.to_string());
+        }
+
+        self.with_timeout(invocation.invoke_async::<_, ()>(&mut conn))
+            .await
+    }
+
+    async fn generations(&self, tags: &[&str]) -> Result<Vec<u64>, CacheError> {
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.manager().await?;
+
+        let mut cmd = redis::cmd("MGET");
+        for tag in tags {
+            cmd.arg(generation_key(tag));
+        }
+
+        let generations = self
+            .with_timeout(cmd.query_async::<_, Vec<Option<u64>>>(&mut conn))
+            .await?;
+
+        Ok(generations
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect())
+    }
+
+    async fn get(&self, cmd: &str, key: &str) -> Result<Option<String>, CacheError> {
+        let mut conn = self.manager().await?;
+
+        let mut cmd = redis::cmd(cmd);
+        cmd.arg(key);
+
+        self.with_timeout(cmd.query_async::<_, Option<String>>(&mut conn))
+            .await
+    }
+
+    async fn invalidate(&self, tags: &[&str]) -> Result<(), CacheError> {
+        let mut conn = self.manager().await?;
+
+        let script = redis::Script::new(INVALIDATE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+
+        for tag in tags {
+            invocation.key(tag_key(tag));
+        }
+
+        for tag in tags {
+            invocation.key(generation_key(tag));
+        }
+
+        self.with_timeout(invocation.invoke_async::<_, ()>(&mut conn))
+            .await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<Option<String>, CacheError> {
+        let mut conn = self.manager().await?;
+        let token = uuid::Uuid::new_v4().simple().to_string();
+
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(lock_key(key))
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis().max(1) as u64);
+
+        let acquired = self
+            .with_timeout(cmd.query_async::<_, Option<String>>(&mut conn))
+            .await?;
+
+        Ok(acquired.map(|_| token))
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), CacheError> {
+        let mut conn = self.manager().await?;
+
+        let script = redis::Script::new(UNLOCK_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(lock_key(key)).arg(token);
+
+        self.with_timeout(invocation.invoke_async::<_, ()>(&mut conn))
+            .await
+    }
+}
+
+// RedisBackend stores entries as strings with `SET EX`, it works with any redis server.
+pub struct RedisBackend(RedisConnection);
+
+impl RedisBackend {
+    pub fn new(conn: RedisConnection) -> Self {
+        Self(conn)
+    }
+}
+
+#[async_trait]
+impl CacheBackend for RedisBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.0.get("GET", key).await
+    }
+
+    async fn generations(&self, tags: &[&str]) -> Result<Vec<u64>, CacheError> {
+        self.0.generations(tags).await
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) -> Result<(), CacheError> {
+        self.0.set(false, key, data, tags, generations, ttl).await
+    }
+
+    async fn invalidate(&self, tags: &[&str]) -> Result<(), CacheError> {
+        self.0.invalidate(tags).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<Option<String>, CacheError> {
+        self.0.lock(key, ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), CacheError> {
+        self.0.unlock(key, token).await
+    }
+}
+
+// RedisJsonBackend stores entries as JSON documents with `JSON.SET`, it requires RedisJSON.
+pub struct RedisJsonBackend(RedisConnection);
+
+impl RedisJsonBackend {
+    pub fn new(conn: RedisConnection) -> Self {
+        Self(conn)
+    }
+}
+
+#[async_trait]
+impl CacheBackend for RedisJsonBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.0.get("JSON.GET", key).await
+    }
+
+    async fn generations(&self, tags: &[&str]) -> Result<Vec<u64>, CacheError> {
+        self.0.generations(tags).await
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        data: &str,
+        tags: &[&str],
+        generations: &[u64],
+        ttl: Duration,
+    ) -> Result<(), CacheError> {
+        self.0.set(true, key, data, tags, generations, ttl).await
+    }
+
+    async fn invalidate(&self, tags: &[&str]) -> Result<(), CacheError> {
+        self.0.invalidate(tags).await
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> Result<Option<String>, CacheError> {
+        self.0.lock(key, ttl).await
+    }
+
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), CacheError> {
+        self.0.unlock(key, token).await
+    }
+}
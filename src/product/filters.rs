@@ -0,0 +1,423 @@
+use serde_json::{json, Value};
+use tokio_postgres::types::{Json, ToSql};
+
+#[derive(thiserror::Error, Debug)]
+pub enum FilterError {
+    #[error("Invalid filter {0}")]
+    InvalidFilter(String),
+
+    #[error("Unknown facet {0}")]
+    UnknownFacet(String),
+
+    #[error("At most {0} facets can be requested")]
+    TooManyFacets(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    // In matches any of comma separated values.
+    In,
+}
+
+impl FilterOp {
+    fn parse(op: &str) -> Option<Self> {
+        let op = match op {
+            "eq" => Self::Eq,
+            "ne" => Self::Ne,
+            "gt" => Self::Gt,
+            "gte" => Self::Gte,
+            "lt" => Self::Lt,
+            "lte" => Self::Lte,
+            "in" => Self::In,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Gt => "gt",
+            Self::Gte => "gte",
+            Self::Lt => "lt",
+            Self::Lte => "lte",
+            Self::In => "in",
+        }
+    }
+
+    fn sql_operator(&self) -> &'static str {
+        match self {
+            Self::Gt => ">",
+            Self::Gte => ">=",
+            Self::Lt => "<",
+            Self::Lte => "<=",
+            _ => "=",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FilterValue {
+    Values(Vec<String>),
+    // Number is compared with plain numbers, or with quantities in its unit if it has one.
+    Number(f64, Option<String>),
+}
+
+// parse_number parses a number with an optional unit, e.g. `16`, `16GB`, `2.5 kg` or `1e3 g`.
+fn parse_number(value: &str) -> Option<(f64, Option<String>)> {
+    let is_number = |c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+');
+    let mut split = value.find(|c| !is_number(c)).unwrap_or(value.len());
+
+    // an exponent is a part of the number, while a unit like `em` starts with `e` as well
+    let rest = &value[split..];
+    if rest.starts_with(['e', 'E'])
+        && rest[1..]
+            .trim_start_matches(['-', '+'])
+            .starts_with(|c: char| c.is_ascii_digit())
+    {
+        split += 1 + rest[1..].find(|c| !is_number(c)).unwrap_or(rest.len() - 1);
+    }
+
+    let (number, unit) = value.split_at(split);
+    let unit = unit.trim();
+
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() => Some((n, (!unit.is_empty()).then(|| unit.to_string()))),
+        _ => None,
+    }
+}
+
+// AttributeFilter is a filter like `attr.color=red`, `attr.weight[lt]=3` or `attr.ram[gte]=16GB`.
+#[derive(Debug, Clone)]
+pub struct AttributeFilter {
+    pub name: String,
+    pub op: FilterOp,
+    pub value: FilterValue,
+}
+
+impl AttributeFilter {
+    // parse returns None for query parameters that aren't attribute filters.
+    fn parse(key: &str, value: &str) -> Option<Result<Self, FilterError>> {
+        let filter = key.strip_prefix("attr.")?;
+
+        let (name, op) = match filter.split_once('[') {
+            Some((name, op)) => match op.strip_suffix(']').and_then(FilterOp::parse) {
+                Some(op) => (name, op),
+                None => return Some(Err(FilterError::InvalidFilter(key.to_string()))),
+            },
+            None => (filter, FilterOp::Eq),
+        };
+
+        if !is_attribute_name(name) {
+            return Some(Err(FilterError::InvalidFilter(key.to_string())));
+        }
+
+        let value = match op {
+            FilterOp::Eq | FilterOp::Ne => FilterValue::Values(vec![value.to_string()]),
+            FilterOp::In => FilterValue::Values(value.split(',').map(str::to_string).collect()),
+            _ => match parse_number(value) {
+                Some((n, unit)) => FilterValue::Number(n, unit),
+                None => return Some(Err(FilterError::InvalidFilter(key.to_string()))),
+            },
+        };
+
+        Some(Ok(Self {
+            name: name.to_string(),
+            op,
+            value,
+        }))
+    }
+
+    // key identifies the filter, e.g. `attr.ram_gb[gte]=16`. Values are quoted,
+    // so their commas and ampersands can't be confused with separators.
+    fn key(&self) -> String {
+        let value = match &self.value {
+            FilterValue::Values(values) => format!("{:?}", values),
+            FilterValue::Number(n, unit) => format!("{}{}", n, unit.as_deref().unwrap_or("")),
+        };
+
+        format!("attr.{}[{}]={}", self.name, self.op.as_str(), value)
+    }
+
+    // condition returns the SQL condition of the filter, its parameters are added to params.
+    fn condition(&self, params: &mut SqlParams) -> String {
+        let name = params.push(self.name.clone());
+
+        match &self.value {
+            FilterValue::Number(n, unit) => {
+                let n = params.push(*n);
+
+                // quantities are compared by their value only if they're in the filter's unit,
+                // numbers without a unit never match quantities
+                let value = match unit {
+                    Some(unit) => format!(
+                        "(CASE WHEN attributes -> {0}::text ->> 'unit' = {1}::text
+                            THEN (attributes -> {0}::text ->> 'value')::float8
+                        END)",
+                        name,
+                        params.push(unit.clone())
+                    ),
+                    None => format!(
+                        "(CASE WHEN jsonb_typeof(attributes -> {0}::text) = 'number'
+                            THEN (attributes ->> {0}::text)::float8
+                        END)",
+                        name
+                    ),
+                };
+
+                format!("{} {} {}::float8", value, self.op.sql_operator(), n)
+            }
+            FilterValue::Values(values) => {
+                // values in the query string are untyped, so every type they can be is matched,
+                // containment queries are able to use the GIN index
+                let conditions = values
+                    .iter()
+                    .flat_map(|v| candidates(&self.name, v))
+                    .map(|c| format!("attributes @> {}::jsonb", params.push(Json(c))))
+                    .collect::<Vec<_>>()
+                    .join(" OR ");
+
+                match self.op {
+                    FilterOp::Ne => format!("NOT ({})", conditions),
+                    _ => format!("({})", conditions),
+                }
+            }
+        }
+    }
+}
+
+// candidates returns JSON documents matching the attribute with the given query string value.
+fn candidates(name: &str, value: &str) -> Vec<Value> {
+    let mut candidates = vec![json!({ name: value })];
+
+    // like numeric filters, quantities match only values with their unit
+    match parse_number(value) {
+        Some((n, Some(unit))) => candidates.push(json!({ name: { "value": n, "unit": unit } })),
+        Some((n, None)) => candidates.push(json!({ name: n })),
+        None => {}
+    }
+
+    match value {
+        "true" => candidates.push(json!({ name: true })),
+        "false" => candidates.push(json!({ name: false })),
+        _ => {}
+    }
+
+    candidates
+}
+
+// SqlParams collects parameters of a dynamically built query.
+#[derive(Default)]
+pub struct SqlParams(Vec<Box<dyn ToSql + Sync + Send>>);
+
+impl SqlParams {
+    // push adds the parameter and returns its placeholder.
+    pub fn push<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.0.push(Box::new(value));
+        format!("${}", self.0.len())
+    }
+
+    pub fn as_refs(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.0
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+// Maximum number of facets computed for a single request, each of them is a query.
+pub const MAX_FACETS: usize = 10;
+
+// Maximum number of values counted in an attribute facet, the most common ones are kept.
+pub const MAX_FACET_VALUES: i64 = 50;
+
+// PRICE_RANGES are lower bounds of price facet ranges, each range ends at the next one.
+pub const PRICE_RANGES: &[f64] = &[0.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0];
+
+// Facet is a kind of counts requested with `?facets=price,categories,status,attr.color`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Facet {
+    // Price counts products in PRICE_RANGES.
+    Price,
+    // Categories counts products in subcategories of the filtered category, or in root categories.
+    Categories,
+    // Status counts published and draft products, it's the only availability products have
+    // until there's stock data.
+    Status,
+    // Attribute counts products with each value of the attribute.
+    Attribute(String),
+}
+
+impl Facet {
+    pub fn parse_list(facets: &str) -> Result<Vec<Self>, FilterError> {
+        let mut parsed = Vec::new();
+
+        for facet in facets.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let facet = match facet {
+                "price" => Self::Price,
+                "categories" => Self::Categories,
+                "status" => Self::Status,
+                _ => match facet.strip_prefix("attr.") {
+                    Some(name) if is_attribute_name(name) => Self::Attribute(name.to_string()),
+                    _ => return Err(FilterError::UnknownFacet(facet.to_string())),
+                },
+            };
+
+            if !parsed.contains(&facet) {
+                parsed.push(facet);
+            }
+        }
+
+        if parsed.len() > MAX_FACETS {
+            return Err(FilterError::TooManyFacets(MAX_FACETS));
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn key(&self) -> String {
+        match self {
+            Self::Price => "price".to_string(),
+            Self::Categories => "categories".to_string(),
+            Self::Status => "status".to_string(),
+            Self::Attribute(name) => format!("attr.{}", name),
+        }
+    }
+}
+
+fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// ProductFilters are filters of the products listing.
+#[derive(Debug, Default, Clone)]
+pub struct ProductFilters {
+    pub attributes: Vec<AttributeFilter>,
+    // price are bounds like `price[gte]=100`.
+    pub price: Vec<(FilterOp, f64)>,
+    // category limits products to the category and its subcategories.
+    pub category: Option<i32>,
+    // status limits products to `Published` or `Draft` ones.
+    pub status: Option<String>,
+}
+
+impl ProductFilters {
+    pub fn from_query(query: &[(String, String)]) -> Result<Self, FilterError> {
+        let mut filters = Self::default();
+
+        for (key, value) in query {
+            if let Some(filter) = AttributeFilter::parse(key, value) {
+                filters.attributes.push(filter?);
+                continue;
+            }
+
+            let invalid = || FilterError::InvalidFilter(key.to_string());
+
+            if key == "category" {
+                filters.category = Some(value.parse().map_err(|_| invalid())?);
+            } else if key == "status" {
+                match value.as_str() {
+                    "Published" | "Draft" => filters.status = Some(value.clone()),
+                    _ => return Err(invalid()),
+                }
+            } else if let Some(op) = key.strip_prefix("price[").and_then(|k| k.strip_suffix(']')) {
+                let op = match FilterOp::parse(op) {
+                    Some(op @ (FilterOp::Gt | FilterOp::Gte | FilterOp::Lt | FilterOp::Lte)) => op,
+                    _ => return Err(invalid()),
+                };
+
+                match value.parse::<f64>() {
+                    Ok(n) if n.is_finite() => filters.price.push((op, n)),
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+
+        Ok(filters)
+    }
+
+    // key identifies the filters regardless of the order and spelling of query parameters,
+    // e.g. `category=01&attr.color=red` and `attr.color[eq]=red&category=1` share a key.
+    pub fn key(&self) -> String {
+        let mut params = self
+            .attributes
+            .iter()
+            .map(AttributeFilter::key)
+            .chain(
+                self.price
+                    .iter()
+                    .map(|(op, n)| format!("price[{}]={}", op.as_str(), n)),
+            )
+            .chain(self.category.map(|c| format!("category={}", c)))
+            .chain(self.status.as_ref().map(|s| format!("status={}", s)))
+            .collect::<Vec<_>>();
+
+        params.sort();
+        params.dedup();
+        params.join("&")
+    }
+
+    // where_clause returns the WHERE clause of the filters (empty without filters),
+    // their parameters are added to params. Filters of the excluded facet are left out,
+    // so the facet counts all of its options.
+    pub fn where_clause(&self, params: &mut SqlParams, exclude: Option<&Facet>) -> String {
+        let mut conditions = Vec::new();
+
+        for filter in &self.attributes {
+            if exclude != Some(&Facet::Attribute(filter.name.clone())) {
+                conditions.push(filter.condition(params));
+            }
+        }
+
+        if exclude != Some(&Facet::Price) {
+            for (op, n) in &self.price {
+                conditions.push(format!(
+                    "price {} {}::float8",
+                    op.sql_operator(),
+                    params.push(*n)
+                ));
+            }
+        }
+
+        if let Some(category) = self
+            .category
+            .filter(|_| exclude != Some(&Facet::Categories))
+        {
+            let category = params.push(category);
+
+            conditions.push(format!(
+                "id IN (SELECT product_id FROM product_categories WHERE category_id = {0}::int OR category_id IN (SELECT id FROM get_subcategories({0})))",
+                category
+            ));
+        }
+
+        // statuses other than Draft are read as Published
+        if let Some(status) = self
+            .status
+            .as_ref()
+            .filter(|_| exclude != Some(&Facet::Status))
+        {
+            conditions.push(match status.as_str() {
+                "Draft" => "status = 'Draft'".to_string(),
+                _ => "status <> 'Draft'".to_string(),
+            });
+        }
+
+        if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        }
+    }
+}
@@ -1,20 +1,42 @@
 use actix_multipart::Multipart;
 use actix_web::{
-    http::{header::ContentType, StatusCode},
+    http::{
+        header::{self, ContentDisposition, ContentType, DispositionParam, DispositionType},
+        StatusCode,
+    },
     web, HttpRequest, HttpResponse, ResponseError,
 };
 use anyhow::Context;
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
 use validator::Validate;
 
-use super::{cache::Cache, store::ProductStore};
-use crate::{product::ProductInsertable, storage::Storage};
+use super::{
+    cache::{product_tag, Cache, CATEGORIES_TAG, PRODUCTS_LIST_TAG},
+    filters::{Facet, ProductFilters},
+    store::{ProductStore, ProductStoreError},
+};
+use crate::{
+    category::{
+        attributes::{normalize_attributes, validate_attributes},
+        store::{CategoryStore, CategoryStoreError},
+    },
+    product::{
+        Asset, AssetByHash, AssetOrder, AssetUpdatable, AssetUploadInsertable, AssetUploadResult,
+        AssetUploadStatus, Product, ProductBySlug, ProductCategories, ProductCategory,
+        ProductInsertable, ProductList,
+    },
+    storage::{signing::SignatureQuery, FileKind, Storage, StorageError, UploadOptions},
+};
 
 #[derive(thiserror::Error, Debug)]
 enum ProductApiError {
-    #[error("Bad request")]
+    #[error("{0}")]
     BadRequest(String),
 
+    #[error("{0}")]
+    Conflict(String),
+
     #[error("Validation failed")]
     ValidationError(#[from] validator::ValidationErrors),
 
@@ -26,6 +48,7 @@ impl ResponseError for ProductApiError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             Self::ValidationError(_) | Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -37,90 +60,344 @@ impl ResponseError for ProductApiError {
             Self::ValidationError(e) => {
                 response.json(json!({"message": "Validation failed", "errors": e.errors()}))
             }
-            Self::BadRequest(_) => response.json(json!({ "message": "Bad request" })),
+            Self::BadRequest(message) => response.json(json!({ "message": message })),
+            Self::Conflict(message) => response.json(json!({ "message": message })),
             Self::Internal(_) => response.json(json!({ "message": "Internal server error" })),
         }
     }
 }
 
+// asset_url returns the url the asset is served at, documents are always downloaded as attachments.
+fn asset_url(storage: &Storage, asset: &Asset) -> String {
+    match asset.kind {
+        FileKind::Image => storage.asset_url(&asset.filename),
+        FileKind::Document => storage.url_for(&format!(
+            "/products/{}/assets/{}/download",
+            asset.product_id, asset.id
+        )),
+    }
+}
+
+fn resolve_asset_url(storage: &Storage, asset: &mut Asset) {
+    asset.url = asset_url(storage, asset);
+
+    if asset.kind == FileKind::Image {
+        asset.variants = storage.image_variant_urls(&asset.filename);
+    }
+}
+
+fn with_url(storage: &Storage, mut asset: Asset) -> Asset {
+    resolve_asset_url(storage, &mut asset);
+    asset
+}
+
+pub(crate) fn resolve_asset_urls(storage: &Storage, product: &mut Product) {
+    for asset in product.assets.iter_mut() {
+        resolve_asset_url(storage, asset);
+    }
+}
+
+// cache_key identifies a cached response by its path and the normalized parameters
+// changing it, parameters the handler ignores don't create separate entries.
+fn cache_key(path: &str, params: &[String]) -> String {
+    let params = params
+        .iter()
+        .filter(|p| !p.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    match params.as_slice() {
+        [] => path.to_string(),
+        _ => format!("{}?{}", path, params.join("&")),
+    }
+}
+
 async fn list_products(
-    req: HttpRequest,
+    query: web::Query<Vec<(String, String)>>,
     product_store: web::Data<ProductStore>,
+    storage: web::Data<Storage>,
     cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
-    let cached = cache
-        .get(req.path())
-        .await
-        .context("Failed to retrieve cached products")?;
+    let filters = ProductFilters::from_query(&query)
+        .map_err(|e| ProductApiError::BadRequest(e.to_string()))?;
 
-    match cached {
-        Some(v) => Ok(HttpResponse::Ok().content_type(ContentType::json()).body(v)),
-        None => {
-            let products = product_store
-                .get_all()
-                .await
-                .context("Failed to get products")?;
+    // facets change the response into `{"products": [...], "facets": {...}}`
+    let mut facets = query
+        .iter()
+        .find(|(k, _)| k == "facets")
+        .map(|(_, v)| Facet::parse_list(v))
+        .transpose()
+        .map_err(|e| ProductApiError::BadRequest(e.to_string()))?;
 
-            let serialized_products = serde_json::to_string(&products).unwrap();
+    // facets are counted independently, their order doesn't change the response
+    if let Some(facets) = &mut facets {
+        facets.sort_by_key(Facet::key);
+    }
 
-            cache
-                .set(req.path(), &serialized_products)
-                .await
-                .context("Failed to cache the products")?;
+    let key = cache_key(
+        "/products",
+        &[
+            filters.key(),
+            facets
+                .as_ref()
+                .map(|f| {
+                    let keys = f.iter().map(Facet::key).collect::<Vec<_>>();
+                    format!("facets={}", keys.join(","))
+                })
+                .unwrap_or_default(),
+        ],
+    );
+
+    let load = move || async move {
+        let (mut products, counts) = product_store
+            .get_all(&filters, facets.as_deref().unwrap_or_default())
+            .await
+            .context("Failed to get products")?;
+
+        products
+            .iter_mut()
+            .for_each(|p| resolve_asset_urls(&storage, p));
+
+        let serialized = match facets {
+            Some(_) => serde_json::to_string(&ProductList {
+                products,
+                facets: counts,
+            })?,
+            None => serde_json::to_string(&products)?,
+        };
+
+        Ok(Some(serialized))
+    };
+
+    let serialized = cache
+        .get_or_load(&key, &[PRODUCTS_LIST_TAG], cache.products_list_ttl(), load)
+        .await?
+        .context("Products weren't loaded")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(serialized))
+}
+
+#[derive(Deserialize)]
+struct ExpandQuery {
+    // expand is a comma separated list of related data to include, e.g. `breadcrumbs`.
+    expand: Option<String>,
+}
+
+// EXPANSIONS are the values `expand` accepts, others are ignored.
+const EXPANSIONS: &[&str] = &["breadcrumbs"];
+
+impl ExpandQuery {
+    fn has(&self, name: &str) -> bool {
+        self.expand
+            .as_deref()
+            .is_some_and(|e| e.split(',').any(|v| v.trim() == name))
+    }
+
+    // key returns the recognized expansions as a query parameter, empty without any.
+    fn key(&self) -> String {
+        let expansions = EXPANSIONS
+            .iter()
+            .copied()
+            .filter(|e| self.has(e))
+            .collect::<Vec<_>>();
 
-            Ok(HttpResponse::Ok().json(products))
+        match expansions.as_slice() {
+            [] => String::new(),
+            _ => format!("expand={}", expansions.join(",")),
         }
     }
 }
 
+// expand_product resolves asset urls and adds related data requested with `?expand=`.
+async fn expand_product(
+    product: &mut Product,
+    query: &ExpandQuery,
+    storage: &Storage,
+    category_store: &CategoryStore,
+) -> Result<(), ProductApiError> {
+    resolve_asset_urls(storage, product);
+
+    if query.has("breadcrumbs") {
+        product.breadcrumbs = Some(match product.category_id {
+            Some(category_id) => category_store
+                .get_path(category_id)
+                .await
+                .context("Failed to get product breadcrumbs")?,
+            None => Vec::new(),
+        });
+    }
+
+    Ok(())
+}
+
 async fn get_product(
-    req: HttpRequest,
     id: web::Path<i32>,
+    query: web::Query<ExpandQuery>,
     product_store: web::Data<ProductStore>,
+    category_store: web::Data<CategoryStore>,
+    storage: web::Data<Storage>,
     cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
+    let id = id.into_inner();
+    let query = query.into_inner();
+
+    let key = cache_key(&format!("/products/{}", id), &[query.key()]);
+
+    let load = move || async move {
+        let product = product_store
+            .get_one(id)
+            .await
+            .context("Failed to get product")?;
+
+        match product {
+            Some(mut p) => {
+                expand_product(&mut p, &query, &storage, &category_store).await?;
+                Ok(Some(serde_json::to_string(&p)?))
+            }
+            None => Ok(None),
+        }
+    };
+
     let cached = cache
-        .get(req.path())
-        .await
-        .context("Failed to retrieve the product from cache")?;
+        .get_or_load(
+            &key,
+            &[&product_tag(id), CATEGORIES_TAG],
+            cache.product_ttl(),
+            load,
+        )
+        .await?;
 
     match cached {
         Some(v) => Ok(HttpResponse::Ok().content_type(ContentType::json()).body(v)),
-        None => {
-            let product = product_store
-                .get_one(id.into_inner())
-                .await
-                .context("Failed to get product")?;
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Product not found"
+        }))),
+    }
+}
 
-            match product {
-                Some(p) => {
-                    let serialized_product = serde_json::to_string(&p).unwrap();
+// get_product_by_slug returns the product, or redirects permanently from a slug it had before.
+async fn get_product_by_slug(
+    req: HttpRequest,
+    slug: web::Path<String>,
+    query: web::Query<ExpandQuery>,
+    product_store: web::Data<ProductStore>,
+    category_store: web::Data<CategoryStore>,
+    storage: web::Data<Storage>,
+) -> Result<HttpResponse, ProductApiError> {
+    let product = product_store
+        .get_by_slug(&slug)
+        .await
+        .context("Failed to get product")?;
 
-                    cache
-                        .set(req.path(), &serialized_product)
-                        .await
-                        .context("Failed to cache the product")?;
+    match product {
+        ProductBySlug::Found(mut p) => {
+            expand_product(&mut p, &query, &storage, &category_store).await?;
 
-                    Ok(HttpResponse::Ok().json(p))
-                }
-                None => Ok(HttpResponse::NotFound().json(json!({
-                    "message": "Product not found"
-                }))),
-            }
+            Ok(HttpResponse::Ok().json(p))
         }
+        ProductBySlug::Moved(current) => {
+            // the query string is kept, so the redirect returns the same response shape
+            let location = match req.query_string() {
+                "" => format!("/products/by-slug/{}", current),
+                query => format!("/products/by-slug/{}?{}", current, query),
+            };
+
+            Ok(HttpResponse::MovedPermanently()
+                .insert_header((header::LOCATION, location))
+                .finish())
+        }
+        ProductBySlug::NotFound => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Product not found"
+        }))),
+    }
+}
+
+// store_error_or turns store errors caused by the request into client errors.
+fn store_error_or(e: ProductStoreError, context: &'static str) -> ProductApiError {
+    match e {
+        ProductStoreError::SlugTaken => ProductApiError::Conflict(e.to_string()),
+        ProductStoreError::CategoryNotFound => ProductApiError::BadRequest(e.to_string()),
+        e => ProductApiError::Internal(anyhow::Error::new(e).context(context)),
+    }
+}
+
+// check_categories rejects repeated categories and more than one primary category.
+fn check_categories(categories: &[ProductCategory]) -> Result<(), ProductApiError> {
+    let mut category_ids = categories.iter().map(|c| c.category_id).collect::<Vec<_>>();
+    category_ids.sort_unstable();
+    category_ids.dedup();
+
+    if category_ids.len() != categories.len() {
+        return Err(ProductApiError::BadRequest(
+            "Categories can't repeat".to_string(),
+        ));
+    }
+
+    if categories.iter().filter(|c| c.is_primary).count() > 1 {
+        return Err(ProductApiError::BadRequest(
+            "Only one category can be primary".to_string(),
+        ));
     }
+
+    Ok(())
+}
+
+// check_attributes validates attributes against the definitions of the given categories
+// and normalizes them for storage.
+async fn check_attributes(
+    category_store: &CategoryStore,
+    category_ids: &[i32],
+    attributes: &mut Map<String, Value>,
+) -> Result<(), ProductApiError> {
+    let definitions = match category_store.get_attributes(category_ids).await {
+        Ok(definitions) => definitions,
+        Err(e @ CategoryStoreError::ConflictingAttribute(_)) => {
+            return Err(ProductApiError::BadRequest(e.to_string()))
+        }
+        Err(e) => {
+            return Err(ProductApiError::Internal(
+                anyhow::Error::new(e).context("Failed to get attribute definitions"),
+            ))
+        }
+    };
+
+    validate_attributes(&definitions, attributes)?;
+    normalize_attributes(&definitions, attributes);
+
+    Ok(())
+}
+
+// invalidate_product purges cached responses including the product, listings include all of them.
+async fn invalidate_product(cache: &Cache, product_id: i32) {
+    cache
+        .invalidate(&[&product_tag(product_id), PRODUCTS_LIST_TAG])
+        .await;
 }
 
 async fn create_product(
     data: web::Json<ProductInsertable>,
     product_store: web::Data<ProductStore>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
     data.validate()?;
 
+    let mut data = data.into_inner();
+
+    let categories = data.categories.as_deref().unwrap_or_default();
+    check_categories(categories)?;
+
+    let category_ids = categories.iter().map(|c| c.category_id).collect::<Vec<_>>();
+    check_attributes(&category_store, &category_ids, &mut data.attributes).await?;
+
     let created = product_store
-        .insert(data.into_inner())
+        .insert(data)
         .await
-        .context("Failed to create product")?;
+        .map_err(|e| store_error_or(e, "Failed to create product"))?;
+
+    cache.invalidate(&[PRODUCTS_LIST_TAG]).await;
 
     Ok(HttpResponse::Created().json(created))
 }
@@ -128,12 +405,17 @@ async fn create_product(
 async fn delete_product(
     id: web::Path<i32>,
     product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
+    let id = id.into_inner();
+
     product_store
-        .delete(id.into_inner())
+        .delete(id)
         .await
         .context("Failed to delete product")?;
 
+    invalidate_product(&cache, id).await;
+
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -141,36 +423,115 @@ async fn update_product(
     id: web::Path<i32>,
     data: web::Json<ProductInsertable>,
     product_store: web::Data<ProductStore>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
-    product_store
-        .update(id.into_inner(), data.into_inner())
+    data.validate()?;
+
+    let id = id.into_inner();
+    let mut data = data.into_inner();
+
+    // attributes are validated against the new categories, or the current ones if they're kept
+    let category_ids = match &data.categories {
+        Some(categories) => {
+            check_categories(categories)?;
+            categories.iter().map(|c| c.category_id).collect()
+        }
+        None => product_store
+            .get_category_ids(id)
+            .await
+            .context("Failed to get product categories")?,
+    };
+
+    check_attributes(&category_store, &category_ids, &mut data.attributes).await?;
+
+    let updated = product_store
+        .update(id, data)
         .await
-        .context("Failed to update product")?;
+        .map_err(|e| store_error_or(e, "Failed to update product"))?;
+
+    if !updated {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "message": "Product not found"
+        })));
+    }
+
+    invalidate_product(&cache, id).await;
 
     Ok(HttpResponse::Ok().finish())
 }
 
-async fn add_product_asset(
-    req: HttpRequest,
+async fn set_product_categories(
     id: web::Path<i32>,
-    multipart: Multipart,
-    storage: web::Data<Storage>,
+    data: web::Json<ProductCategories>,
     product_store: web::Data<ProductStore>,
+    category_store: web::Data<CategoryStore>,
+    cache: web::Data<Cache>,
 ) -> Result<HttpResponse, ProductApiError> {
-    // check if content_length isn't too large
+    data.validate()?;
+    check_categories(&data.categories)?;
+
+    let id = id.into_inner();
+
+    let product = product_store
+        .get_one(id)
+        .await
+        .context("Failed to get product")?;
 
+    let product = match product {
+        Some(product) => product,
+        None => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "message": "Product not found"
+            })))
+        }
+    };
+
+    // the product has to satisfy the attribute definitions of its new categories
+    let category_ids = data
+        .categories
+        .iter()
+        .map(|c| c.category_id)
+        .collect::<Vec<_>>();
+
+    let mut attributes = match product.attributes {
+        Value::Object(attributes) => attributes,
+        _ => Map::new(),
+    };
+
+    check_attributes(&category_store, &category_ids, &mut attributes).await?;
+
+    let categories = product_store
+        .set_categories(id, data.into_inner().categories, &attributes)
+        .await
+        .map_err(|e| store_error_or(e, "Failed to set product categories"))?;
+
+    match categories {
+        Some(categories) => {
+            invalidate_product(&cache, id).await;
+
+            Ok(HttpResponse::Ok().json(categories))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Product not found"
+        }))),
+    }
+}
+
+// check_content_length rejects requests without content-length or with a body larger than limit.
+fn check_content_length(req: &HttpRequest, limit: u64) -> Result<(), ProductApiError> {
     if let Some(conent_length) = req.headers().get("content-length") {
         if conent_length
             .to_str()
             .context("Failed to parse content-length to str")?
             .parse::<u64>()
             .context("Failed to parse content-length to u64")?
-            // 2 MB
-            > 1024 * 1024 * 2
+            > limit
         {
-            return Err(ProductApiError::BadRequest(
-                "File can't be bigger than 1MB".to_string(),
-            ));
+            return Err(ProductApiError::BadRequest(format!(
+                "Request can't be bigger than {} bytes",
+                limit
+            )));
         }
     } else {
         return Err(ProductApiError::BadRequest(
@@ -178,27 +539,496 @@ async fn add_product_asset(
         ));
     }
 
+    Ok(())
+}
+
+async fn add_product_asset(
+    req: HttpRequest,
+    id: web::Path<i32>,
+    options: web::Query<UploadOptions>,
+    multipart: Multipart,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    // check if content_length isn't too large
+
+    check_content_length(&req, storage.max_file_size())?;
+
     // save uploaded file
 
-    let asset_filename = storage
-        .save_image(multipart)
+    let file = storage
+        .save_file(multipart, options.into_inner())
         .await
-        .context("Failed to save image")?;
+        .context("Failed to save file")?;
 
     match product_store
-        .add_asset(id.to_owned(), &asset_filename)
+        .add_asset(id.to_owned(), &file)
         .await
         .context("Failed to add asset")
     {
-        Ok(asset) => Ok(HttpResponse::Created().json(asset)),
+        Ok(asset) => {
+            invalidate_product(&cache, *id).await;
+
+            Ok(HttpResponse::Created().json(with_url(&storage, asset)))
+        }
+        // the file is left to the gc, removing it here would race with uploads deduplicated into it
+        Err(e) => Err(ProductApiError::Internal(e)),
+    }
+}
+
+async fn add_product_asset_by_hash(
+    id: web::Path<i32>,
+    data: web::Json<AssetByHash>,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    let id = id.into_inner();
+
+    let asset = product_store
+        .add_asset_by_hash(id, &data.hash)
+        .await
+        .context("Failed to add asset")?;
+
+    match asset {
+        Some(asset) => {
+            invalidate_product(&cache, id).await;
+
+            Ok(HttpResponse::Created().json(with_url(&storage, asset)))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Content with given hash not found"
+        }))),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchUploadQuery {
+    #[serde(default)]
+    atomic: bool,
+
+    #[serde(default)]
+    keep_original: bool,
+}
+
+async fn add_product_assets(
+    req: HttpRequest,
+    id: web::Path<i32>,
+    query: web::Query<BatchUploadQuery>,
+    multipart: Multipart,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    check_content_length(
+        &req,
+        storage.max_files_per_request() as u64 * storage.max_file_size(),
+    )?;
+
+    let product_id = id.into_inner();
+
+    let options = UploadOptions {
+        keep_original: query.keep_original,
+    };
+
+    let uploaded = match storage.save_files(multipart, options).await {
+        Ok(uploaded) => uploaded,
+        Err(e @ StorageError::TooManyFiles(_)) => {
+            return Err(ProductApiError::BadRequest(e.to_string()))
+        }
+        Err(e) => {
+            return Err(ProductApiError::Internal(
+                anyhow::Error::new(e).context("Failed to save files"),
+            ))
+        }
+    };
+
+    if query.atomic {
+        let files = uploaded
+            .iter()
+            .filter_map(|f| f.result.as_ref().ok())
+            .collect::<Vec<_>>();
+
+        // all-or-nothing: a single failed file discards the whole batch,
+        // saved files are left to the gc as they may already be used by other assets
+        if files.len() != uploaded.len() {
+            let results = uploaded
+                .into_iter()
+                .map(|f| AssetUploadResult::Failed {
+                    filename: f.original_filename,
+                    error: match f.result {
+                        Ok(_) => "Batch aborted".to_string(),
+                        Err(e) => e.to_string(),
+                    },
+                })
+                .collect::<Vec<_>>();
+
+            return Ok(HttpResponse::BadRequest().json(results));
+        }
+
+        return match product_store.add_assets(product_id, &files).await {
+            Ok(assets) => {
+                invalidate_product(&cache, product_id).await;
+
+                let results = uploaded
+                    .into_iter()
+                    .zip(assets)
+                    .map(|(f, asset)| AssetUploadResult::Created {
+                        filename: f.original_filename,
+                        asset: with_url(&storage, asset),
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok(HttpResponse::Created().json(results))
+            }
+            Err(e) => Err(ProductApiError::Internal(
+                anyhow::Error::new(e).context("Failed to add assets"),
+            )),
+        };
+    }
+
+    let mut results = Vec::with_capacity(uploaded.len());
+
+    for f in uploaded {
+        let result = match f.result {
+            Ok(file) => match product_store.add_asset(product_id, &file).await {
+                Ok(asset) => AssetUploadResult::Created {
+                    filename: f.original_filename,
+                    asset,
+                },
+                Err(e) => {
+                    log::error!("Failed to add asset: {:?}", e);
+
+                    AssetUploadResult::Failed {
+                        filename: f.original_filename,
+                        error: "Failed to add asset".to_string(),
+                    }
+                }
+            },
+            Err(e) => AssetUploadResult::Failed {
+                filename: f.original_filename,
+                error: e.to_string(),
+            },
+        };
+
+        results.push(result);
+    }
+
+    if results
+        .iter()
+        .any(|r| matches!(r, AssetUploadResult::Created { .. }))
+    {
+        invalidate_product(&cache, product_id).await;
+    }
+
+    if results
+        .iter()
+        .all(|r| matches!(r, AssetUploadResult::Created { .. }))
+    {
+        Ok(HttpResponse::Created().json(results))
+    } else {
+        Ok(HttpResponse::MultiStatus().json(results))
+    }
+}
+
+async fn update_product_asset(
+    path: web::Path<(i32, i32)>,
+    data: web::Json<AssetUpdatable>,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    data.validate()?;
+
+    let (product_id, asset_id) = path.into_inner();
+
+    let asset = product_store
+        .update_asset(product_id, asset_id, data.into_inner())
+        .await
+        .context("Failed to update asset")?;
+
+    match asset {
+        Some(asset) => {
+            invalidate_product(&cache, product_id).await;
+
+            Ok(HttpResponse::Ok().json(with_url(&storage, asset)))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Asset not found"
+        }))),
+    }
+}
+
+async fn delete_product_asset(
+    path: web::Path<(i32, i32)>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    let (product_id, asset_id) = path.into_inner();
+
+    let asset = product_store
+        .delete_asset(product_id, asset_id)
+        .await
+        .context("Failed to delete asset")?;
+
+    // the file isn't removed here, it may still be used by other assets or be deduplicated
+    // into by an upload in progress, the gc removes it once it's unreferenced past its grace period
+    match asset {
+        Some(_) => {
+            invalidate_product(&cache, product_id).await;
+
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Asset not found"
+        }))),
+    }
+}
+
+async fn download_product_asset(
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+    query: web::Query<SignatureQuery>,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+) -> Result<HttpResponse, ProductApiError> {
+    if !storage.authorize(req.path(), &query) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "message": "Invalid or expired signature"
+        })));
+    }
+
+    let (product_id, asset_id) = path.into_inner();
+
+    let asset = product_store
+        .get_asset(product_id, asset_id)
+        .await
+        .context("Failed to get asset")?;
+
+    let asset = match asset {
+        Some(asset) => asset,
+        None => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "message": "Asset not found"
+            })))
+        }
+    };
+
+    let file = storage
+        .open_file(&asset.filename)
+        .await
+        .context("Failed to open asset file")?;
+
+    // the file is stored under its hash, so the original name is restored for the download
+    let file = file.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(
+            asset.original_filename.unwrap_or(asset.filename),
+        )],
+    });
+
+    Ok(storage.file_response(file, &req))
+}
+
+async fn reorder_product_assets(
+    id: web::Path<i32>,
+    data: web::Json<AssetOrder>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    let id = id.into_inner();
+
+    let reordered = product_store
+        .reorder_assets(id, &data.asset_ids)
+        .await
+        .context("Failed to reorder assets")?;
+
+    if !reordered {
+        return Err(ProductApiError::BadRequest(
+            "asset_ids must list every asset of the product exactly once".to_string(),
+        ));
+    }
+
+    invalidate_product(&cache, id).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// is_invalid_file tells apart files rejected by validation from storage failures.
+fn is_invalid_file(e: &StorageError) -> bool {
+    matches!(
+        e,
+        StorageError::InvalidMimeType | StorageError::FileTooLarge | StorageError::Processing(_)
+    )
+}
+
+// create_product_asset_upload starts a direct upload, the file is then sent with
+// a PUT request to the returned url and turned into an asset by the completion call.
+async fn create_product_asset_upload(
+    id: web::Path<i32>,
+    data: web::Json<AssetUploadInsertable>,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+) -> Result<HttpResponse, ProductApiError> {
+    data.validate()?;
+
+    if let Err(e) = storage.check_upload(&data.content_type, data.size as u64) {
+        return Ok(HttpResponse::BadRequest().json(json!({ "message": e.to_string() })));
+    }
+
+    let product_id = id.into_inner();
+
+    let upload = product_store
+        .create_asset_upload(product_id, &data, storage.upload_ttl())
+        .await
+        .context("Failed to create upload")?;
+
+    // only the local storage is supported, so the upload url is the api itself
+    // and the one-time token in its path is the credential
+    match upload {
+        Some(upload) => {
+            let upload_path = format!("/products/{}/assets/uploads/{}", product_id, upload.token);
+
+            Ok(HttpResponse::Created().json(json!({
+                "upload_id": upload.token,
+                "method": "PUT",
+                "upload_url": storage.absolute_url(&upload_path),
+                "headers": { "Content-Type": upload.content_type },
+                "complete_url": storage.absolute_url(&format!("{}/complete", upload_path)),
+                "expires_at": upload.expires_at,
+            })))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "message": "Product not found"
+        }))),
+    }
+}
+
+async fn put_product_asset_upload(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    payload: web::Payload,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+) -> Result<HttpResponse, ProductApiError> {
+    let (product_id, token) = path.into_inner();
+
+    let upload = product_store
+        .claim_asset_upload(
+            product_id,
+            &token,
+            AssetUploadStatus::Pending,
+            AssetUploadStatus::Uploading,
+        )
+        .await
+        .context("Failed to claim upload")?;
+
+    let upload = match upload {
+        Some(upload) => upload,
+        None => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "message": "Upload not found, expired or already used"
+            })))
+        }
+    };
+
+    let result = match check_content_length(&req, upload.size as u64) {
+        Ok(_) => storage
+            .save_upload(&token, payload, upload.size as u64)
+            .await
+            .map_err(|e| match e {
+                StorageError::FileTooLarge => ProductApiError::BadRequest(e.to_string()),
+                e => ProductApiError::Internal(
+                    anyhow::Error::new(e).context("Failed to save upload"),
+                ),
+            }),
+        Err(e) => Err(e),
+    };
+
+    // a failed upload can be retried with the same token until it expires
+    let status = match result {
+        Ok(_) => AssetUploadStatus::Uploaded,
+        Err(_) => AssetUploadStatus::Pending,
+    };
+
+    product_store
+        .set_asset_upload_status(&token, status)
+        .await
+        .context("Failed to update upload")?;
+
+    result?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn complete_product_asset_upload(
+    path: web::Path<(i32, String)>,
+    options: web::Query<UploadOptions>,
+    storage: web::Data<Storage>,
+    product_store: web::Data<ProductStore>,
+    cache: web::Data<Cache>,
+) -> Result<HttpResponse, ProductApiError> {
+    let (product_id, token) = path.into_inner();
+
+    let upload = product_store
+        .claim_asset_upload(
+            product_id,
+            &token,
+            AssetUploadStatus::Uploaded,
+            AssetUploadStatus::Completing,
+        )
+        .await
+        .context("Failed to claim upload")?;
+
+    let upload = match upload {
+        Some(upload) => upload,
+        None => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "message": "Upload not found, expired or not uploaded yet"
+            })))
+        }
+    };
+
+    // the uploaded file is consumed either way, so the token can't be used again
+    let file = storage
+        .finalize_upload(
+            &token,
+            &upload.content_type,
+            upload.original_filename.as_deref(),
+            options.into_inner(),
+        )
+        .await;
+
+    product_store
+        .delete_asset_upload(&token)
+        .await
+        .context("Failed to delete upload")?;
+
+    let file = match file {
+        Ok(file) => file,
+        Err(e) if is_invalid_file(&e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({ "message": e.to_string() })))
+        }
         Err(e) => {
-            storage
-                .delete_image(&asset_filename)
-                .await
-                .expect("Failed to remove the file");
+            return Err(ProductApiError::Internal(
+                anyhow::Error::new(e).context("Failed to finalize upload"),
+            ))
+        }
+    };
 
-            Err(ProductApiError::Internal(e))
+    match product_store
+        .add_asset(product_id, &file)
+        .await
+        .context("Failed to add asset")
+    {
+        Ok(asset) => {
+            invalidate_product(&cache, product_id).await;
+
+            Ok(HttpResponse::Created().json(with_url(&storage, asset)))
         }
+        // the file is left to the gc, removing it here would race with uploads deduplicated into it
+        Err(e) => Err(ProductApiError::Internal(e)),
     }
 }
 
@@ -210,6 +1040,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                     .route(web::get().to(list_products))
                     .route(web::post().to(create_product)),
             )
+            // registered before the `{id}` scope, which would match `by-slug` too
+            .route("/by-slug/{slug}", web::get().to(get_product_by_slug))
             .service(
                 web::scope("{id}")
                     .service(
@@ -218,7 +1050,32 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                             .route(web::put().to(update_product))
                             .route(web::delete().to(delete_product)),
                     )
-                    .route("/assets", web::post().to(add_product_asset)),
+                    .route("/categories", web::put().to(set_product_categories))
+                    .route("/assets", web::post().to(add_product_asset))
+                    .route("/assets/by-hash", web::post().to(add_product_asset_by_hash))
+                    .route("/assets/batch", web::post().to(add_product_assets))
+                    .route("/assets/order", web::put().to(reorder_product_assets))
+                    .route(
+                        "/assets/uploads",
+                        web::post().to(create_product_asset_upload),
+                    )
+                    .route(
+                        "/assets/uploads/{token}",
+                        web::put().to(put_product_asset_upload),
+                    )
+                    .route(
+                        "/assets/uploads/{token}/complete",
+                        web::post().to(complete_product_asset_upload),
+                    )
+                    .route(
+                        "/assets/{asset_id}/download",
+                        web::get().to(download_product_asset),
+                    )
+                    .service(
+                        web::resource("/assets/{asset_id}")
+                            .route(web::put().to(update_product_asset))
+                            .route(web::delete().to(delete_product_asset)),
+                    ),
             ),
     );
 }
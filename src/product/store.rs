@@ -1,8 +1,17 @@
-use super::{Asset, Product, ProductInsertable};
+use super::filters::{Facet, ProductFilters, SqlParams, MAX_FACET_VALUES, PRICE_RANGES};
+use super::{
+    Asset, AssetFile, AssetUpdatable, AssetUpload, AssetUploadInsertable, AssetUploadStatus,
+    CategoryCount, FacetCounts, PriceRangeCount, Product, ProductBySlug, ProductCategory,
+    ProductInsertable, ValueCount,
+};
+use crate::{slug, storage::StoredFile};
 use deadpool_postgres::{Pool, Transaction};
 use futures::{stream::FuturesUnordered, TryStreamExt};
-use tokio_pg_mapper::FromTokioPostgresRow;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, time::Duration};
+use tokio_postgres::{error::SqlState, types::Json, IsolationLevel};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
 pub enum ProductStoreError {
     #[error("Database query failed")]
@@ -13,6 +22,22 @@ pub enum ProductStoreError {
 
     #[error("Database connection failed")]
     ConnectionFailed(#[from] deadpool_postgres::PoolError),
+
+    #[error("Product with this slug already exists")]
+    SlugTaken,
+
+    #[error("Category not found")]
+    CategoryNotFound,
+}
+
+// slug_taken_or maps a violation of the unique slug constraints to SlugTaken,
+// it can still happen when two requests race past the slug check.
+fn slug_taken_or(e: tokio_postgres::Error) -> ProductStoreError {
+    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        ProductStoreError::SlugTaken
+    } else {
+        e.into()
+    }
 }
 
 #[derive(Clone)]
@@ -31,33 +56,93 @@ impl ProductStore {
         transaction: &Transaction<'a>,
     ) -> Result<Vec<Asset>, ProductStoreError> {
         let assets_rows = transaction
-            .query("SELECT * FROM assets WHERE product_id = $1", &[&product_id])
+            .query(
+                "SELECT * FROM assets WHERE product_id = $1 ORDER BY position, id",
+                &[&product_id],
+            )
             .await?;
 
         assets_rows
             .iter()
-            .map(|row| Asset::from_row_ref(row).map_err(|e| ProductStoreError::MappingFailed(e)))
+            .map(|row| Asset::try_from(row).map_err(ProductStoreError::MappingFailed))
             .collect()
     }
 
-    pub async fn get_all(&self) -> Result<Vec<Product>, ProductStoreError> {
+    async fn get_product_categories<'a>(
+        &self,
+        product_id: i32,
+        transaction: &Transaction<'a>,
+    ) -> Result<Vec<ProductCategory>, ProductStoreError> {
+        let rows = transaction
+            .query(
+                "SELECT * FROM product_categories WHERE product_id = $1 ORDER BY is_primary DESC, category_id",
+                &[&product_id],
+            )
+            .await?;
+
+        rows.iter()
+            .map(|row| ProductCategory::try_from(row).map_err(ProductStoreError::MappingFailed))
+            .collect()
+    }
+
+    // load_relations adds assets and categories to the product.
+    async fn load_relations<'a>(
+        &self,
+        product: &mut Product,
+        transaction: &Transaction<'a>,
+    ) -> Result<(), ProductStoreError> {
+        product.assets = self.get_product_assets(product.id, transaction).await?;
+        product.categories = self.get_product_categories(product.id, transaction).await?;
+        product.category_id = product
+            .categories
+            .iter()
+            .find(|c| c.is_primary)
+            .map(|c| c.category_id);
+
+        Ok(())
+    }
+
+    // get_all returns products matching the filters and counts of the requested facets,
+    // read from a single snapshot so the counts agree with the products.
+    pub async fn get_all(
+        &self,
+        filters: &ProductFilters,
+        facets: &[Facet],
+    ) -> Result<(Vec<Product>, BTreeMap<String, FacetCounts>), ProductStoreError> {
         let mut conn = self.db_pool.get().await?;
-        let transaction = conn.transaction().await?;
+        let transaction = conn
+            .build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()
+            .await?;
 
         let result = async {
-            let product_rows = transaction.query("SELECT * FROM products", &[]).await?;
+            let mut params = SqlParams::default();
+            let where_clause = filters.where_clause(&mut params, None);
+
+            let product_rows = transaction
+                .query(
+                    &format!("SELECT * FROM products {} ORDER BY id", where_clause),
+                    &params.as_refs(),
+                )
+                .await?;
             let transaction_ref = &transaction;
 
-            Ok(product_rows
+            let products = product_rows
                 .iter()
                 .map(|row| async move {
                     let mut product = Product::try_from(row)?;
-                    product.assets = self.get_product_assets(product.id, transaction_ref).await?;
+                    self.load_relations(&mut product, transaction_ref).await?;
                     Ok::<_, ProductStoreError>(product)
                 })
                 .collect::<FuturesUnordered<_>>()
                 .try_collect()
-                .await?)
+                .await?;
+
+            let counts = self.count_facets(filters, facets, &transaction).await?;
+
+            Ok((products, counts))
         }
         .await;
 
@@ -70,6 +155,154 @@ impl ProductStore {
         result
     }
 
+    // count_facets counts products matching the filters for each facet, with one query per facet.
+    // The facet's own filters are ignored, so all of its options get counted.
+    async fn count_facets<'a>(
+        &self,
+        filters: &ProductFilters,
+        facets: &[Facet],
+        transaction: &Transaction<'a>,
+    ) -> Result<BTreeMap<String, FacetCounts>, ProductStoreError> {
+        let mut counts = BTreeMap::new();
+
+        for facet in facets {
+            let mut params = SqlParams::default();
+            let where_clause = filters.where_clause(&mut params, Some(facet));
+
+            let facet_counts = match facet {
+                Facet::Price => {
+                    let min = PRICE_RANGES.to_vec();
+                    let max = PRICE_RANGES
+                        .iter()
+                        .skip(1)
+                        .map(|&m| Some(m))
+                        .chain([None])
+                        .collect::<Vec<_>>();
+
+                    let query = format!(
+                        "SELECT r.min, r.max, COUNT(p.id) AS count
+                        FROM unnest({}::float8[], {}::float8[]) AS r(min, max)
+                        LEFT JOIN (SELECT id, price FROM products {}) AS p
+                            ON p.price >= r.min AND (r.max IS NULL OR p.price < r.max)
+                        GROUP BY r.min, r.max
+                        ORDER BY r.min",
+                        params.push(min),
+                        params.push(max),
+                        where_clause
+                    );
+
+                    let rows = transaction.query(&query, &params.as_refs()).await?;
+
+                    FacetCounts::Price(
+                        rows.iter()
+                            .map(|row| {
+                                Ok(PriceRangeCount {
+                                    min: row.try_get("min")?,
+                                    max: row.try_get("max")?,
+                                    count: row.try_get("count")?,
+                                })
+                            })
+                            .collect::<Result<_, tokio_postgres::Error>>()?,
+                    )
+                }
+                Facet::Categories => {
+                    // children of the filtered category, root categories without the filter
+                    let query = format!(
+                        "SELECT c.id, c.name, c.slug, COUNT(DISTINCT pc.product_id) AS count
+                        FROM categories AS c
+                        CROSS JOIN LATERAL (
+                            SELECT c.id UNION ALL SELECT id FROM get_subcategories(c.id)
+                        ) AS subtree(id)
+                        JOIN product_categories AS pc ON pc.category_id = subtree.id
+                        WHERE c.parent_id IS NOT DISTINCT FROM {}::int
+                            AND pc.product_id IN (SELECT id FROM products {})
+                        GROUP BY c.id
+                        ORDER BY c.position, c.id",
+                        params.push(filters.category),
+                        where_clause
+                    );
+
+                    let rows = transaction.query(&query, &params.as_refs()).await?;
+
+                    FacetCounts::Categories(
+                        rows.iter()
+                            .map(|row| {
+                                Ok(CategoryCount {
+                                    id: row.try_get("id")?,
+                                    name: row.try_get("name")?,
+                                    slug: row.try_get("slug")?,
+                                    count: row.try_get("count")?,
+                                })
+                            })
+                            .collect::<Result<_, tokio_postgres::Error>>()?,
+                    )
+                }
+                Facet::Status => {
+                    let query = format!(
+                        "SELECT s.status AS value, COUNT(p.id) AS count
+                        FROM unnest(ARRAY['Published', 'Draft']) AS s(status)
+                        LEFT JOIN (SELECT id, status FROM products {}) AS p
+                            ON (p.status = 'Draft') = (s.status = 'Draft')
+                        GROUP BY s.status
+                        ORDER BY s.status DESC",
+                        where_clause
+                    );
+
+                    let rows = transaction.query(&query, &params.as_refs()).await?;
+
+                    FacetCounts::Values(
+                        rows.iter()
+                            .map(|row| {
+                                Ok(ValueCount {
+                                    value: Value::String(row.try_get("value")?),
+                                    count: row.try_get("count")?,
+                                })
+                            })
+                            .collect::<Result<_, tokio_postgres::Error>>()?,
+                    )
+                }
+                Facet::Attribute(name) => {
+                    let name = params.push(name.clone());
+                    // products without the attribute (or with null) have no value to count
+                    let condition = format!("jsonb_typeof(attributes -> {}::text) <> 'null'", name);
+                    let where_clause = if where_clause.is_empty() {
+                        format!("WHERE {}", condition)
+                    } else {
+                        format!("{} AND {}", where_clause, condition)
+                    };
+
+                    let query = format!(
+                        "SELECT attributes -> {}::text AS value, COUNT(*) AS count
+                        FROM products {}
+                        GROUP BY 1
+                        ORDER BY count DESC, value
+                        LIMIT {}",
+                        name,
+                        where_clause,
+                        params.push(MAX_FACET_VALUES)
+                    );
+
+                    let rows = transaction.query(&query, &params.as_refs()).await?;
+
+                    FacetCounts::Values(
+                        rows.iter()
+                            .map(|row| {
+                                Ok(ValueCount {
+                                    value: row.try_get("value")?,
+                                    count: row.try_get("count")?,
+                                })
+                            })
+                            .collect::<Result<_, tokio_postgres::Error>>()?,
+                    )
+                }
+            };
+
+            counts.insert(facet.key(), facet_counts);
+        }
+
+        Ok(counts)
+    }
+
     pub async fn get_one(&self, id: i32) -> Result<Option<Product>, ProductStoreError> {
         let mut conn = self.db_pool.get().await?;
         let transaction = conn.transaction().await?;
@@ -82,7 +315,7 @@ impl ProductStore {
             match row {
                 Some(row) => {
                     let mut product = Product::try_from(&row)?;
-                    product.assets = self.get_product_assets(product.id, &transaction).await?;
+                    self.load_relations(&mut product, &transaction).await?;
 
                     Ok(Some(product))
                 }
@@ -100,33 +333,375 @@ impl ProductStore {
         result
     }
 
-    pub async fn insert(&self, product: ProductInsertable) -> Result<Product, ProductStoreError> {
+    // get_by_category returns products in the category, or in the category and its
+    // subcategories, ordered by their position in the listing.
+    pub async fn get_by_category(
+        &self,
+        category_id: i32,
+        include_subcategories: bool,
+    ) -> Result<Vec<Product>, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            // a product in many of the subcategories is listed once, at its first position
+            let product_rows = transaction
+                .query(
+                    "SELECT p.* FROM products AS p
+                     JOIN (
+                         SELECT product_id, MIN(position) AS position FROM product_categories
+                         WHERE category_id = $1 OR ($2 AND category_id IN (SELECT id FROM get_subcategories($1)))
+                         GROUP BY product_id
+                     ) AS pc ON pc.product_id = p.id
+                     ORDER BY pc.position, p.id",
+                    &[&category_id, &include_subcategories],
+                )
+                .await?;
+
+            let mut products = Vec::with_capacity(product_rows.len());
+
+            for row in &product_rows {
+                let mut product = Product::try_from(row)?;
+                self.load_relations(&mut product, &transaction).await?;
+                products.push(product);
+            }
+
+            Ok(products)
+        }
+        .await;
+
+        if result.is_err() {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+
+        result
+    }
+
+    // replace_categories replaces categories of the product. The first one becomes
+    // primary when none is marked.
+    async fn replace_categories<'a>(
+        &self,
+        product_id: i32,
+        categories: &mut [ProductCategory],
+        transaction: &Transaction<'a>,
+    ) -> Result<(), ProductStoreError> {
+        if !categories.iter().any(|c| c.is_primary) {
+            if let Some(first) = categories.first_mut() {
+                first.is_primary = true;
+            }
+        }
+
+        transaction
+            .execute(
+                "DELETE FROM product_categories WHERE product_id = $1",
+                &[&product_id],
+            )
+            .await?;
+
+        for category in categories.iter() {
+            transaction
+                .execute(
+                    "INSERT INTO product_categories (product_id, category_id, is_primary, position)
+                     VALUES ($1, $2, $3, $4)",
+                    &[
+                        &product_id,
+                        &category.category_id,
+                        &category.is_primary,
+                        &category.position,
+                    ],
+                )
+                .await
+                .map_err(|e| {
+                    if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                        ProductStoreError::CategoryNotFound
+                    } else {
+                        e.into()
+                    }
+                })?;
+        }
+
+        Ok(())
+    }
+
+    // get_category_ids returns ids of the product's categories.
+    pub async fn get_category_ids(&self, product_id: i32) -> Result<Vec<i32>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let rows = conn
+            .query(
+                "SELECT category_id FROM product_categories WHERE product_id = $1",
+                &[&product_id],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| row.try_get("category_id"))
+            .collect::<Result<_, _>>()?)
+    }
+
+    // set_categories replaces categories of the product along with its attributes, normalized
+    // for the new categories. Returns None if the product doesn't exist.
+    pub async fn set_categories(
+        &self,
+        product_id: i32,
+        mut categories: Vec<ProductCategory>,
+        attributes: &Map<String, Value>,
+    ) -> Result<Option<Vec<ProductCategory>>, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let exists = transaction
+                .query_opt(
+                    "SELECT id FROM products WHERE id = $1 FOR UPDATE",
+                    &[&product_id],
+                )
+                .await?
+                .is_some();
+
+            if !exists {
+                return Ok(None);
+            }
+
+            transaction
+                .execute(
+                    "UPDATE products SET attributes = $1 WHERE id = $2",
+                    &[&Json(attributes), &product_id],
+                )
+                .await?;
+
+            self.replace_categories(product_id, &mut categories, &transaction)
+                .await?;
+
+            Ok(Some(
+                self.get_product_categories(product_id, &transaction)
+                    .await?,
+            ))
+        }
+        .await;
+
+        if matches!(result, Ok(Some(_))) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // get_by_slug looks the product up by its current slug, or by a slug it had before.
+    pub async fn get_by_slug(&self, slug: &str) -> Result<ProductBySlug, ProductStoreError> {
         let conn = self.db_pool.get().await?;
 
         let row = conn
+            .query_opt("SELECT id FROM products WHERE slug = $1", &[&slug])
+            .await?;
+
+        if let Some(row) = row {
+            return match self.get_one(row.try_get("id")?).await? {
+                Some(product) => Ok(ProductBySlug::Found(product)),
+                None => Ok(ProductBySlug::NotFound),
+            };
+        }
+
+        let row = conn
+            .query_opt(
+                "SELECT p.slug FROM product_slug_redirects AS r JOIN products AS p ON p.id = r.product_id WHERE r.slug = $1",
+                &[&slug],
+            )
+            .await?;
+
+        match row {
+            Some(row) => Ok(ProductBySlug::Moved(row.try_get("slug")?)),
+            None => Ok(ProductBySlug::NotFound),
+        }
+    }
+
+    // is_slug_taken checks if another product uses the slug, now or as a redirect.
+    async fn is_slug_taken<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        slug: &str,
+        product_id: Option<i32>,
+    ) -> Result<bool, ProductStoreError> {
+        let row = transaction
             .query_one(
-                "INSERT INTO products (name, price) VALUES ($1, $2) RETURNING *",
-                &[&product.name, &product.price],
+                "SELECT EXISTS (SELECT 1 FROM products WHERE slug = $1 AND id IS DISTINCT FROM $2)
+                     OR EXISTS (SELECT 1 FROM product_slug_redirects WHERE slug = $1 AND product_id IS DISTINCT FROM $2)",
+                &[&slug, &product_id],
             )
             .await?;
 
-        Ok(Product::try_from(&row)?)
+        Ok(row.try_get(0)?)
+    }
+
+    // resolve_slug checks the requested slug, or generates one from the name
+    // with a numeric suffix when it's already taken.
+    async fn resolve_slug<'a>(
+        &self,
+        transaction: &Transaction<'a>,
+        requested: Option<&str>,
+        name: &str,
+        product_id: Option<i32>,
+    ) -> Result<String, ProductStoreError> {
+        if let Some(requested) = requested {
+            if self
+                .is_slug_taken(transaction, requested, product_id)
+                .await?
+            {
+                return Err(ProductStoreError::SlugTaken);
+            }
+
+            return Ok(requested.to_string());
+        }
+
+        let base = slug::slugify(name, "product");
+        let mut candidate = base.clone();
+        let mut n = 1;
+
+        while self
+            .is_slug_taken(transaction, &candidate, product_id)
+            .await?
+        {
+            n += 1;
+            candidate = slug::with_suffix(&base, n);
+        }
+
+        Ok(candidate)
+    }
+
+    pub async fn insert(
+        &self,
+        mut product: ProductInsertable,
+    ) -> Result<Product, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let slug = self
+                .resolve_slug(&transaction, product.slug.as_deref(), &product.name, None)
+                .await?;
+
+            let row = transaction
+                .query_one(
+                    "INSERT INTO products (name, price, slug, attributes) VALUES ($1, $2, $3, $4) RETURNING *",
+                    &[
+                        &product.name,
+                        &product.price,
+                        &slug,
+                        &Json(&product.attributes),
+                    ],
+                )
+                .await
+                .map_err(slug_taken_or)?;
+
+            let mut created = Product::try_from(&row)?;
+
+            if let Some(categories) = product.categories.as_mut() {
+                self.replace_categories(created.id, categories, &transaction)
+                    .await?;
+                self.load_relations(&mut created, &transaction).await?;
+            }
+
+            Ok(created)
+        }
+        .await;
+
+        if result.is_err() {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+
+        result
     }
 
+    // update changes the product, renamed products get a new slug unless one is given
+    // and the old slug is kept as a redirect. Returns false if the product doesn't exist.
     pub async fn update(
         &self,
         id: i32,
-        product: ProductInsertable,
-    ) -> Result<(), ProductStoreError> {
-        let conn = self.db_pool.get().await?;
+        mut product: ProductInsertable,
+    ) -> Result<bool, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
 
-        conn.execute(
-            "UPDATE products SET name = $1, price = $2 WHERE id = $3 RETURNING *",
-            &[&product.name, &product.price, &id],
-        )
-        .await?;
+        let result = async {
+            let row = transaction
+                .query_opt(
+                    "SELECT name, slug FROM products WHERE id = $1 FOR UPDATE",
+                    &[&id],
+                )
+                .await?;
 
-        Ok(())
+            let (name, old_slug): (String, String) = match row {
+                Some(row) => (row.try_get("name")?, row.try_get("slug")?),
+                None => return Ok(false),
+            };
+
+            let slug = match &product.slug {
+                Some(slug) => {
+                    self.resolve_slug(&transaction, Some(slug), &product.name, Some(id))
+                        .await?
+                }
+                None if name != product.name => {
+                    self.resolve_slug(&transaction, None, &product.name, Some(id))
+                        .await?
+                }
+                None => old_slug.clone(),
+            };
+
+            if slug != old_slug {
+                // going back to a previous slug makes it current again
+                transaction
+                    .execute(
+                        "DELETE FROM product_slug_redirects WHERE slug = $1 AND product_id = $2",
+                        &[&slug, &id],
+                    )
+                    .await?;
+
+                transaction
+                    .execute(
+                        "INSERT INTO product_slug_redirects (slug, product_id) VALUES ($1, $2)",
+                        &[&old_slug, &id],
+                    )
+                    .await
+                    .map_err(slug_taken_or)?;
+            }
+
+            transaction
+                .execute(
+                    "UPDATE products SET name = $1, price = $2, slug = $3, attributes = $4 WHERE id = $5",
+                    &[
+                        &product.name,
+                        &product.price,
+                        &slug,
+                        &Json(&product.attributes),
+                        &id,
+                    ],
+                )
+                .await
+                .map_err(slug_taken_or)?;
+
+            if let Some(categories) = product.categories.as_mut() {
+                self.replace_categories(id, categories, &transaction)
+                    .await?;
+            }
+
+            Ok(true)
+        }
+        .await;
+
+        if matches!(result, Ok(true)) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
     }
 
     pub async fn delete(&self, id: i32) -> Result<(), ProductStoreError> {
@@ -141,17 +716,337 @@ impl ProductStore {
     pub async fn add_asset(
         &self,
         product_id: i32,
-        asset_filename: &String,
+        file: &StoredFile,
     ) -> Result<Asset, ProductStoreError> {
         let conn = self.db_pool.get().await?;
 
+        // new assets are appended after the existing ones
         let row = conn
             .query_one(
-                "INSERT INTO assets (product_id, filename) VALUES ($1, $2) RETURNING *",
-                &[&product_id, &asset_filename],
+                "INSERT INTO assets (product_id, filename, hash, kind, original_filename, position)
+                 VALUES ($1, $2, $3, $4, $5, (SELECT COALESCE(MAX(position) + 1, 0) FROM assets WHERE product_id = $1))
+                 RETURNING *",
+                &[
+                    &product_id,
+                    &file.filename,
+                    &file.hash,
+                    &file.kind.as_str(),
+                    &file.original_filename,
+                ],
+            )
+            .await?;
+
+        Ok(Asset::try_from(&row)?)
+    }
+
+    // add_asset_by_hash adds an asset reusing already stored content.
+    // Returns None if no asset with the given hash exists.
+    pub async fn add_asset_by_hash(
+        &self,
+        product_id: i32,
+        hash: &str,
+    ) -> Result<Option<Asset>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt(
+                "INSERT INTO assets (product_id, filename, hash, kind, original_filename, position)
+                 SELECT $1, filename, hash, kind, original_filename, (SELECT COALESCE(MAX(position) + 1, 0) FROM assets WHERE product_id = $1)
+                 FROM assets WHERE hash = $2 LIMIT 1
+                 RETURNING *",
+                &[&product_id, &hash],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(Asset::try_from).transpose()?)
+    }
+
+    pub async fn get_asset(
+        &self,
+        product_id: i32,
+        asset_id: i32,
+    ) -> Result<Option<Asset>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt(
+                "SELECT * FROM assets WHERE id = $1 AND product_id = $2",
+                &[&asset_id, &product_id],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(Asset::try_from).transpose()?)
+    }
+
+    pub async fn get_asset_files(&self) -> Result<Vec<AssetFile>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let rows = conn
+            .query("SELECT id, product_id, filename FROM assets", &[])
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(AssetFile {
+                    asset_id: row.try_get("id")?,
+                    product_id: row.try_get("product_id")?,
+                    filename: row.try_get("filename")?,
+                })
+            })
+            .collect()
+    }
+
+    // add_assets adds all assets in a single transaction, so either all of them are added or none.
+    pub async fn add_assets(
+        &self,
+        product_id: i32,
+        files: &[&StoredFile],
+    ) -> Result<Vec<Asset>, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let mut assets = Vec::with_capacity(files.len());
+
+            for file in files {
+                let row = transaction
+                    .query_one(
+                        "INSERT INTO assets (product_id, filename, hash, kind, original_filename, position)
+                         VALUES ($1, $2, $3, $4, $5, (SELECT COALESCE(MAX(position) + 1, 0) FROM assets WHERE product_id = $1))
+                         RETURNING *",
+                        &[
+                            &product_id,
+                            &file.filename,
+                            &file.hash,
+                            &file.kind.as_str(),
+                            &file.original_filename,
+                        ],
+                    )
+                    .await?;
+
+                assets.push(Asset::try_from(&row)?);
+            }
+
+            Ok(assets)
+        }
+        .await;
+
+        if result.is_err() {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+
+        result
+    }
+
+    // delete_asset removes the asset row and returns it, its file is left to the gc.
+    pub async fn delete_asset(
+        &self,
+        product_id: i32,
+        asset_id: i32,
+    ) -> Result<Option<Asset>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt(
+                "DELETE FROM assets WHERE id = $1 AND product_id = $2 RETURNING *",
+                &[&asset_id, &product_id],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(Asset::try_from).transpose()?)
+    }
+
+    pub async fn update_asset(
+        &self,
+        product_id: i32,
+        asset_id: i32,
+        asset: AssetUpdatable,
+    ) -> Result<Option<Asset>, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            // only one asset can be primary, so the previous one has to be demoted first
+            if asset.is_primary {
+                transaction
+                    .execute(
+                        "UPDATE assets SET is_primary = FALSE WHERE product_id = $1 AND id <> $2 AND is_primary",
+                        &[&product_id, &asset_id],
+                    )
+                    .await?;
+            }
+
+            let row = transaction
+                .query_opt(
+                    "UPDATE assets SET alt_text = $1, is_primary = $2 WHERE id = $3 AND product_id = $4 RETURNING *",
+                    &[&asset.alt_text, &asset.is_primary, &asset_id, &product_id],
+                )
+                .await?;
+
+            Ok(row.as_ref().map(Asset::try_from).transpose()?)
+        }
+        .await;
+
+        if matches!(result, Ok(Some(_))) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // reorder_assets sets asset positions to their index in asset_ids.
+    // Returns false if asset_ids isn't a permutation of all the product's assets.
+    pub async fn reorder_assets(
+        &self,
+        product_id: i32,
+        asset_ids: &[i32],
+    ) -> Result<bool, ProductStoreError> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let result = async {
+            let assets_count: i64 = transaction
+                .query_one(
+                    "SELECT COUNT(*) FROM assets WHERE product_id = $1",
+                    &[&product_id],
+                )
+                .await?
+                .try_get(0)?;
+
+            let updated = transaction
+                .execute(
+                    "UPDATE assets SET position = o.position - 1
+                     FROM unnest($2::int[]) WITH ORDINALITY AS o(id, position)
+                     WHERE assets.id = o.id AND assets.product_id = $1",
+                    &[&product_id, &asset_ids],
+                )
+                .await?;
+
+            Ok::<_, ProductStoreError>(
+                updated as usize == asset_ids.len() && assets_count as usize == asset_ids.len(),
+            )
+        }
+        .await;
+
+        if matches!(result, Ok(true)) {
+            transaction.commit().await?;
+        } else {
+            transaction.rollback().await?;
+        }
+
+        result
+    }
+
+    // create_asset_upload creates a direct upload with a random token valid for ttl.
+    // Returns None if the product doesn't exist.
+    pub async fn create_asset_upload(
+        &self,
+        product_id: i32,
+        upload: &AssetUploadInsertable,
+        ttl: Duration,
+    ) -> Result<Option<AssetUpload>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let ttl_secs = ttl.as_secs_f64();
+
+        let row = conn
+            .query_opt(
+                "INSERT INTO asset_uploads (token, product_id, content_type, original_filename, size, expires_at)
+                 SELECT $1, id, $3, $4, $5, now() + make_interval(secs => $6)
+                 FROM products WHERE id = $2
+                 RETURNING *",
+                &[
+                    &token,
+                    &product_id,
+                    &upload.content_type,
+                    &upload.filename,
+                    &upload.size,
+                    &ttl_secs,
+                ],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(AssetUpload::try_from).transpose()?)
+    }
+
+    // claim_asset_upload moves an unexpired upload of the product from one status to another.
+    // Returns None if there's no such upload, so every step can be done only once.
+    pub async fn claim_asset_upload(
+        &self,
+        product_id: i32,
+        token: &str,
+        from: AssetUploadStatus,
+        to: AssetUploadStatus,
+    ) -> Result<Option<AssetUpload>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_opt(
+                "UPDATE asset_uploads
+                 SET status = $4, completing_since = CASE WHEN $4 = 'completing' THEN now() END
+                 WHERE token = $1 AND product_id = $2 AND status = $3 AND expires_at > now()
+                 RETURNING *",
+                &[&token, &product_id, &from.as_str(), &to.as_str()],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(AssetUpload::try_from).transpose()?)
+    }
+
+    pub async fn set_asset_upload_status(
+        &self,
+        token: &str,
+        status: AssetUploadStatus,
+    ) -> Result<(), ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute(
+            "UPDATE asset_uploads
+             SET status = $1, completing_since = CASE WHEN $1 = 'completing' THEN now() END
+             WHERE token = $2",
+            &[&status.as_str(), &token],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_asset_upload(&self, token: &str) -> Result<(), ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute("DELETE FROM asset_uploads WHERE token = $1", &[&token])
+            .await?;
+
+        Ok(())
+    }
+
+    // delete_expired_asset_uploads removes expired uploads and returns their tokens,
+    // uploads that are being completed are left alone until completion_timeout passes.
+    pub async fn delete_expired_asset_uploads(
+        &self,
+        completion_timeout: Duration,
+    ) -> Result<Vec<String>, ProductStoreError> {
+        let conn = self.db_pool.get().await?;
+        let timeout_secs = completion_timeout.as_secs_f64();
+
+        let rows = conn
+            .query(
+                "DELETE FROM asset_uploads
+                 WHERE expires_at <= now()
+                 AND (status <> 'completing' OR completing_since <= now() - make_interval(secs => $1))
+                 RETURNING token",
+                &[&timeout_secs],
             )
             .await?;
 
-        Ok(Asset::from_row(row)?)
+        Ok(rows
+            .iter()
+            .map(|row| row.try_get("token"))
+            .collect::<Result<_, _>>()?)
     }
 }
@@ -0,0 +1,71 @@
+use validator::ValidationError;
+
+pub const MAX_SLUG_LENGTH: usize = 100;
+
+// transliterate maps Polish letters to their ASCII counterparts.
+fn transliterate(c: char) -> Option<char> {
+    let mapped = match c {
+        'ą' | 'Ą' => 'a',
+        'ć' | 'Ć' => 'c',
+        'ę' | 'Ę' => 'e',
+        'ł' | 'Ł' => 'l',
+        'ń' | 'Ń' => 'n',
+        'ó' | 'Ó' => 'o',
+        'ś' | 'Ś' => 's',
+        'ź' | 'Ź' | 'ż' | 'Ż' => 'z',
+        c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+        _ => return None,
+    };
+
+    Some(mapped)
+}
+
+// slugify turns a name into a lowercase, dash separated ASCII slug,
+// e.g. `Łóżka piętrowe` becomes `lozka-pietrowe`. Returns fallback for names without
+// any letters or digits.
+pub fn slugify(name: &str, fallback: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+
+    for c in name.chars() {
+        match transliterate(c) {
+            Some(c) => slug.push(c),
+            None if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+            None => {}
+        }
+    }
+
+    slug.truncate(MAX_SLUG_LENGTH);
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        fallback.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// with_suffix makes a slug unique by appending a number, keeping it within MAX_SLUG_LENGTH.
+pub fn with_suffix(slug: &str, n: u32) -> String {
+    let suffix = format!("-{}", n);
+    let base = &slug[..slug.len().min(MAX_SLUG_LENGTH - suffix.len())];
+
+    format!("{}{}", base.trim_end_matches('-'), suffix)
+}
+
+// validate_slug accepts slugs in the shape produced by slugify.
+pub fn validate_slug(slug: &str) -> Result<(), ValidationError> {
+    let valid = !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LENGTH
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.contains("--")
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("slug"))
+    }
+}
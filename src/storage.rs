@@ -1,7 +1,96 @@
-use actix_multipart::Multipart;
-use futures::{StreamExt, TryStreamExt};
-use std::{io::Write, ops::Deref};
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart};
+use actix_web::{
+    http::header::{self, ContentDisposition, DispositionType},
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use futures::{Stream, StreamExt, TryStreamExt};
+use image::ImageFormat;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    path::Path,
+    time::{Duration, SystemTime},
+};
 use thiserror::Error;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use self::{
+    processing::ProcessingError,
+    signing::{SignatureQuery, UrlSigner},
+};
+
+pub mod handlers;
+pub mod processing;
+pub mod signing;
+
+// Maximum size of a single uploaded image (2 MB).
+pub const MAX_IMAGE_SIZE: u64 = 1024 * 1024 * 2;
+
+// Extension of documents with an accepted type missing from DOCUMENT_TYPES.
+const DEFAULT_DOCUMENT_EXTENSION: &str = "bin";
+
+// DocumentType is a known document mime type, files are stored with its extension
+// and have to start with one of its signatures. Text formats have no signature.
+struct DocumentType {
+    mime: &'static str,
+    extension: &'static str,
+    signatures: &'static [&'static [u8]],
+}
+
+const DOCUMENT_TYPES: &[DocumentType] = &[
+    DocumentType {
+        mime: "application/pdf",
+        extension: "pdf",
+        signatures: &[b"%PDF-"],
+    },
+    DocumentType {
+        mime: "image/vnd.dwg",
+        extension: "dwg",
+        signatures: &[b"AC1"],
+    },
+    DocumentType {
+        mime: "image/vnd.dxf",
+        extension: "dxf",
+        signatures: &[],
+    },
+    DocumentType {
+        mime: "model/step",
+        extension: "step",
+        signatures: &[b"ISO-10303-21;"],
+    },
+    DocumentType {
+        mime: "model/iges",
+        extension: "igs",
+        signatures: &[],
+    },
+    DocumentType {
+        mime: "application/zip",
+        extension: "zip",
+        signatures: &[b"PK\x03\x04", b"PK\x05\x06"],
+    },
+];
+
+// Number of leading bytes read to check the content of a file against its declared type.
+const SIGNATURE_LEN: usize = 64;
+
+// Extensions of files served inline, everything else is only served as an attachment.
+const INLINE_EXTENSIONS: &[&str] = &["jpeg", "png"];
+
+pub const ASSETS_DIR: &str = "./assets";
+
+// Uploads are written here first and moved into ASSETS_DIR once their hash is known.
+pub const TMP_DIR: &str = "./assets/.tmp";
+
+// Direct uploads are kept here until they are finalized.
+pub const UPLOADS_DIR: &str = "./assets/.uploads";
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -11,56 +100,677 @@ pub enum StorageError {
     #[error("Multipart error")]
     Multipart(#[from] actix_multipart::MultipartError),
 
+    #[error("Payload error")]
+    Payload(#[from] actix_web::error::PayloadError),
+
     #[error("Multipart field missing")]
     MultipartFieldMissing(String),
 
     #[error("Invalid mime type")]
     InvalidMimeType,
+
+    #[error("File too large")]
+    FileTooLarge,
+
+    #[error("Too many files, at most {0} are allowed")]
+    TooManyFiles(usize),
+
+    #[error(transparent)]
+    Processing(#[from] ProcessingError),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Image,
+    // Document is a downloadable attachment, e.g. a PDF manual.
+    Document,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Image => "image",
+            FileKind::Document => "document",
+        }
+    }
+}
+
+// UploadOptions control how uploaded files are processed.
+#[derive(Deserialize, Default, Clone, Copy)]
+pub struct UploadOptions {
+    // keep_original skips the image normalization, e.g. for archival copies.
+    #[serde(default)]
+    pub keep_original: bool,
+}
+
+// StoredFile is a file stored under the SHA-256 hash of its content.
+pub struct StoredFile {
+    pub filename: String,
+    pub hash: String,
+    pub kind: FileKind,
+    // original_filename is the sanitized name the file was uploaded with.
+    pub original_filename: Option<String>,
+}
+
+// FileInfo describes a file present in ASSETS_DIR.
+pub struct FileInfo {
+    pub filename: String,
+    pub modified: SystemTime,
+}
+
+// UploadedFile is the outcome of saving a single multipart field.
+pub struct UploadedFile {
+    pub original_filename: Option<String>,
+    pub result: Result<StoredFile, StorageError>,
+}
+
+// document_type returns the known document type of the mime type.
+fn document_type(mime: &Mime) -> Option<&'static DocumentType> {
+    DOCUMENT_TYPES
+        .iter()
+        .find(|t| t.mime.eq_ignore_ascii_case(mime.essence_str()))
+}
+
+// check_content tells if the leading bytes of a file match its declared type,
+// the extension is never taken from the client so it can't make the file served as e.g. HTML.
+fn check_content(mime: &Mime, format: Option<ImageFormat>, head: &[u8]) -> bool {
+    match format {
+        Some(format) => image::guess_format(head).ok() == Some(format),
+        None => document_type(mime)
+            .filter(|t| !t.signatures.is_empty())
+            .is_none_or(|t| t.signatures.iter().any(|s| head.starts_with(s))),
+    }
+}
+
+async fn exists(path: &str) -> std::io::Result<bool> {
+    match fs::metadata(path).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
 }
 
 #[derive(Clone)]
-pub struct Storage;
+pub struct StorageConfig {
+    pub max_files_per_request: usize,
+    // document_mime_types lists mime types accepted as documents, images are always JPEG or PNG.
+    pub document_mime_types: Vec<String>,
+    pub max_document_size: u64,
+    // url_signer is set in private mode, where assets are only served through signed urls.
+    pub url_signer: Option<UrlSigner>,
+    // public_base_url is the origin the api is reachable at, e.g. `https://shop.example.com`.
+    pub public_base_url: String,
+    // asset_base_url is where ASSETS_DIR is served from, e.g. a CDN forwarding to `/assets`.
+    pub asset_base_url: String,
+    // image_variants maps variant names to url templates with `{url}` and `{filename}` placeholders.
+    pub image_variants: Vec<(String, String)>,
+    // upload_ttl is how long a direct upload can be used after it's created.
+    pub upload_ttl: Duration,
+}
+
+#[derive(Clone)]
+pub struct Storage {
+    config: StorageConfig,
+}
 
 impl Storage {
-    pub fn new() -> Self {
-        Storage
+    pub fn new(config: StorageConfig) -> Self {
+        Storage { config }
+    }
+
+    pub fn absolute_url(&self, path: &str) -> String {
+        format!("{}{}", self.config.public_base_url, path)
+    }
+
+    // url_for returns the absolute url of an api path, signed in private mode.
+    pub fn url_for(&self, path: &str) -> String {
+        match &self.config.url_signer {
+            Some(signer) => format!("{}?{}", self.absolute_url(path), signer.query(path)),
+            None => self.absolute_url(path),
+        }
+    }
+
+    // authorize checks the signature of a request path, every path is allowed in public mode.
+    pub fn authorize(&self, path: &str, query: &SignatureQuery) -> bool {
+        match (&self.config.url_signer, query.expires, &query.signature) {
+            (None, _, _) => true,
+            (Some(signer), Some(expires), Some(signature)) => {
+                signer.verify(path, expires, signature)
+            }
+            _ => false,
+        }
     }
 
-    pub async fn save_image(&self, mut multipart: Multipart) -> Result<String, StorageError> {
+    // asset_url returns the absolute url the file is served at, signed in private mode.
+    pub fn asset_url(&self, filename: &str) -> String {
+        let url = format!("{}/{}", self.config.asset_base_url, filename);
+
+        match &self.config.url_signer {
+            Some(signer) => format!("{}?{}", url, signer.query(&format!("/assets/{}", filename))),
+            None => url,
+        }
+    }
+
+    // image_variant_urls returns the url of every configured variant of the image.
+    // Templates can use {url}, {filename}, and {path} with {query} to place the signature
+    // elsewhere; parameters after `{url}?` are joined with the signature in private mode.
+    pub fn image_variant_urls(&self, filename: &str) -> BTreeMap<String, String> {
+        let url = self.asset_url(filename);
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+        let url_with_params = match query {
+            "" => format!("{}?", url),
+            _ => format!("{}&", url),
+        };
+
+        self.config
+            .image_variants
+            .iter()
+            .map(|(name, template)| {
+                let variant_url = template
+                    .replace("{url}?", &url_with_params)
+                    .replace("{url}", &url)
+                    .replace("{path}", path)
+                    .replace("{query}", query)
+                    .replace("{filename}", filename);
+
+                (name.clone(), variant_url)
+            })
+            .collect()
+    }
+
+    pub fn max_files_per_request(&self) -> usize {
+        self.config.max_files_per_request
+    }
+
+    // max_file_size returns the size limit of the largest accepted file kind.
+    pub fn max_file_size(&self) -> u64 {
+        MAX_IMAGE_SIZE.max(self.config.max_document_size)
+    }
+
+    pub fn upload_ttl(&self) -> Duration {
+        self.config.upload_ttl
+    }
+
+    // check_upload validates the declared type and size of a direct upload before it's created.
+    pub fn check_upload(&self, content_type: &str, size: u64) -> Result<(), StorageError> {
+        let mime = content_type
+            .parse::<Mime>()
+            .map_err(|_| StorageError::InvalidMimeType)?;
+
+        let (_, _, _, max_size) = self.classify(&mime)?;
+
+        if size > max_size {
+            return Err(StorageError::FileTooLarge);
+        }
+
+        Ok(())
+    }
+
+    pub async fn save_file(
+        &self,
+        mut multipart: Multipart,
+        options: UploadOptions,
+    ) -> Result<StoredFile, StorageError> {
         let field_name = "payload".to_string();
 
-        while let Some(Ok(mut field)) = multipart.next().await {
+        while let Some(Ok(field)) = multipart.next().await {
             let content_disposition = field.content_disposition();
 
             if Some(field_name.deref()) != content_disposition.get_name() {
                 continue;
             }
 
-            let mime = field.content_type();
+            return self.save_field(field, options).await;
+        }
+
+        Err(StorageError::MultipartFieldMissing(field_name))
+    }
 
-            match (mime.type_(), mime.subtype()) {
-                (mime::IMAGE, mime::JPEG) | (mime::IMAGE, mime::PNG) => {
-                    let filename = format!("{}.jpeg", uuid::Uuid::new_v4());
-                    let file_path = format!("./assets/{}", filename);
+    // save_files saves every field named `payload`. Errors of a single file are
+    // reported in its result, while errors of the whole request fail it.
+    pub async fn save_files(
+        &self,
+        mut multipart: Multipart,
+        options: UploadOptions,
+    ) -> Result<Vec<UploadedFile>, StorageError> {
+        let field_name = "payload";
+        let mut uploaded: Vec<UploadedFile> = Vec::new();
 
-                    let mut f = std::fs::File::create(file_path)?;
-                    while let Some(chunk) = field.try_next().await? {
-                        f = f.write_all(&chunk).map(|_| f)?;
-                    }
+        let result = async {
+            while let Some(field) = multipart.try_next().await? {
+                let content_disposition = field.content_disposition();
 
-                    return Ok(filename);
+                if Some(field_name) != content_disposition.get_name() {
+                    continue;
                 }
-                _ => return Err(StorageError::InvalidMimeType),
+
+                if uploaded.len() == self.config.max_files_per_request {
+                    return Err(StorageError::TooManyFiles(
+                        self.config.max_files_per_request,
+                    ));
+                }
+
+                let original_filename = content_disposition.get_filename().map(str::to_string);
+                let result = self.save_field(field, options).await;
+
+                uploaded.push(UploadedFile {
+                    original_filename,
+                    result,
+                });
             }
+
+            if uploaded.is_empty() {
+                return Err(StorageError::MultipartFieldMissing(field_name.to_string()));
+            }
+
+            Ok(())
         }
+        .await;
 
-        Err(StorageError::MultipartFieldMissing(field_name))
+        // saved files are left to the gc, another upload may have been deduplicated into them
+        result.map(|_| uploaded)
     }
 
-    pub async fn delete_image(&self, filename: &str) -> Result<(), StorageError> {
-        let file_path = format!("./assets/{}", filename);
-        std::fs::remove_file(file_path)?;
+    // classify returns the kind of a file with given mime type, along with its
+    // extension, image format (for images) and size limit.
+    fn classify(
+        &self,
+        mime: &Mime,
+    ) -> Result<(FileKind, String, Option<ImageFormat>, u64), StorageError> {
+        match (mime.type_(), mime.subtype()) {
+            (mime::IMAGE, mime::JPEG) => Ok((
+                FileKind::Image,
+                "jpeg".to_string(),
+                Some(ImageFormat::Jpeg),
+                MAX_IMAGE_SIZE,
+            )),
+            (mime::IMAGE, mime::PNG) => Ok((
+                FileKind::Image,
+                "png".to_string(),
+                Some(ImageFormat::Png),
+                MAX_IMAGE_SIZE,
+            )),
+            _ if self
+                .config
+                .document_mime_types
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(mime.essence_str())) =>
+            {
+                Ok((
+                    FileKind::Document,
+                    document_type(mime)
+                        .map_or(DEFAULT_DOCUMENT_EXTENSION, |t| t.extension)
+                        .to_string(),
+                    None,
+                    self.config.max_document_size,
+                ))
+            }
+            _ => Err(StorageError::InvalidMimeType),
+        }
+    }
+
+    async fn save_field(
+        &self,
+        mut field: Field,
+        options: UploadOptions,
+    ) -> Result<StoredFile, StorageError> {
+        let original_filename = field
+            .content_disposition()
+            .get_filename()
+            .map(sanitize_filename::sanitize);
+
+        let mime = field.content_type().clone();
+        let (kind, extension, format, max_size) = self.classify(&mime)?;
+
+        let tmp_path = format!("{}/{}", TMP_DIR, uuid::Uuid::new_v4());
+
+        let result = async {
+            let mut f = fs::File::create(&tmp_path).await?;
+            let hash = Self::write_stream(&mut field, &mut f, max_size).await?;
+            drop(f);
+
+            // the declared type isn't trusted, the content has to match it
+            if !check_content(&mime, format, &Self::read_head(&tmp_path).await?) {
+                return Err(StorageError::InvalidMimeType);
+            }
+
+            // only images are normalized, documents are always kept untouched
+            match format {
+                Some(format) if !options.keep_original => {
+                    Self::normalize_file(&tmp_path, format).await
+                }
+                _ => Ok(hash),
+            }
+        }
+        .await;
+
+        // don't leave partially written files behind
+        let hash = match result {
+            Ok(hash) => hash,
+            Err(e) => {
+                fs::remove_file(&tmp_path).await?;
+
+                return Err(e);
+            }
+        };
+
+        let filename = format!("{}.{}", hash, extension);
+        Self::move_into_assets(&tmp_path, &filename).await?;
+
+        Ok(StoredFile {
+            filename,
+            hash,
+            kind,
+            original_filename,
+        })
+    }
+
+    // move_into_assets moves a complete file into ASSETS_DIR under filename,
+    // the source is removed instead if the same content is already there.
+    async fn move_into_assets(path: &str, filename: &str) -> Result<(), StorageError> {
+        let file_path = format!("{}/{}", ASSETS_DIR, filename);
+
+        // an existing file is marked as recently used, so the gc keeps it until the asset
+        // referencing it is added; if the gc took it already, the new copy takes its place
+        let deduplicated = Self::touch(&file_path).await?;
+
+        if deduplicated {
+            fs::remove_file(path).await?;
+        } else {
+            // the rename is atomic, so a file is either complete or doesn't exist at all
+            fs::rename(path, &file_path).await?;
+            fs::File::open(ASSETS_DIR).await?.sync_all().await?;
+        }
 
         Ok(())
     }
+
+    // touch sets the modification time of the file to now, returns false if it doesn't exist.
+    async fn touch(path: &str) -> Result<bool, StorageError> {
+        let f = match fs::OpenOptions::new().append(true).open(path).await {
+            Ok(f) => f.into_std().await,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        tokio::task::spawn_blocking(move || f.set_modified(SystemTime::now()))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        // the gc may have moved the file aside after it was opened and missed the new
+        // modification time, see delete_orphan; it's then treated as already removed
+        Ok(exists(path).await?)
+    }
+
+    // write_stream writes the stream into f and returns the hex encoded SHA-256 of its content.
+    // The content is synced to disk before returning.
+    async fn write_stream<S, E>(
+        stream: &mut S,
+        f: &mut fs::File,
+        max_size: u64,
+    ) -> Result<String, StorageError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        StorageError: From<E>,
+    {
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+
+        while let Some(chunk) = stream.try_next().await? {
+            size += chunk.len() as u64;
+
+            if size > max_size {
+                return Err(StorageError::FileTooLarge);
+            }
+
+            hasher.update(&chunk);
+            f.write_all(&chunk).await?;
+        }
+
+        f.sync_all().await?;
+
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    // save_upload stores the body of a direct upload until it's finalized.
+    // The body can't be larger than the size declared when the upload was created.
+    pub async fn save_upload<S, E>(
+        &self,
+        token: &str,
+        mut payload: S,
+        size: u64,
+    ) -> Result<(), StorageError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        StorageError: From<E>,
+    {
+        let tmp_path = format!("{}/{}", TMP_DIR, uuid::Uuid::new_v4());
+
+        let result = async {
+            let mut f = fs::File::create(&tmp_path).await?;
+            Self::write_stream(&mut payload, &mut f, size).await?;
+
+            fs::rename(&tmp_path, format!("{}/{}", UPLOADS_DIR, token)).await?;
+            fs::File::open(UPLOADS_DIR).await?.sync_all().await?;
+
+            Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            fs::remove_file(&tmp_path).await.ok();
+        }
+
+        result
+    }
+
+    // finalize_upload validates a direct upload against its declared content type
+    // and stores it the same way as files uploaded through multipart requests.
+    pub async fn finalize_upload(
+        &self,
+        token: &str,
+        content_type: &str,
+        original_filename: Option<&str>,
+        options: UploadOptions,
+    ) -> Result<StoredFile, StorageError> {
+        let upload_path = format!("{}/{}", UPLOADS_DIR, token);
+
+        let result = async {
+            let mime = content_type
+                .parse::<Mime>()
+                .map_err(|_| StorageError::InvalidMimeType)?;
+
+            let original_filename = original_filename.map(sanitize_filename::sanitize);
+
+            let (kind, extension, format, max_size) = self.classify(&mime)?;
+
+            if fs::metadata(&upload_path).await?.len() > max_size {
+                return Err(StorageError::FileTooLarge);
+            }
+
+            let data = fs::read(&upload_path).await?;
+
+            // the declared type isn't trusted, the content has to match it
+            if !check_content(&mime, format, &data) {
+                return Err(StorageError::InvalidMimeType);
+            }
+
+            let hash = match format {
+                Some(format) => {
+                    if options.keep_original {
+                        hex::encode(Sha256::digest(&data))
+                    } else {
+                        Self::normalize_file(&upload_path, format).await?
+                    }
+                }
+                None => hex::encode(Sha256::digest(&data)),
+            };
+
+            let filename = format!("{}.{}", hash, extension);
+            Self::move_into_assets(&upload_path, &filename).await?;
+
+            Ok(StoredFile {
+                filename,
+                hash,
+                kind,
+                original_filename,
+            })
+        }
+        .await;
+
+        if result.is_err() {
+            self.delete_upload(token).await?;
+        }
+
+        result
+    }
+
+    // delete_upload removes the body of a direct upload, if it was uploaded.
+    pub async fn delete_upload(&self, token: &str) -> Result<(), StorageError> {
+        match fs::remove_file(format!("{}/{}", UPLOADS_DIR, token)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // normalize_file replaces the image at path with its normalized version
+    // and returns the hex encoded SHA-256 of the new content.
+    async fn normalize_file(path: &str, format: ImageFormat) -> Result<String, StorageError> {
+        let data = fs::read(path).await?;
+
+        // decoding and encoding is CPU heavy, so it mustn't block the actix worker
+        let normalized =
+            tokio::task::spawn_blocking(move || processing::normalize_image(&data, format))
+                .await
+                .map_err(std::io::Error::other)??;
+
+        let mut f = fs::File::create(path).await?;
+        f.write_all(&normalized).await?;
+        f.sync_all().await?;
+
+        Ok(hex::encode(Sha256::digest(&normalized)))
+    }
+
+    // open_file opens a stored file, only images are served inline.
+    pub async fn open_file(&self, filename: &str) -> Result<NamedFile, StorageError> {
+        let file = NamedFile::open_async(format!("{}/{}", ASSETS_DIR, filename)).await?;
+
+        let inline = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| INLINE_EXTENSIONS.contains(&e));
+
+        if inline {
+            return Ok(file);
+        }
+
+        Ok(file.set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![],
+        }))
+    }
+
+    // file_response serves the file, browsers mustn't guess a different type from its content.
+    pub fn file_response(&self, file: NamedFile, req: &HttpRequest) -> HttpResponse {
+        let mut response = file.into_response(req);
+
+        response.headers_mut().insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            header::HeaderValue::from_static("nosniff"),
+        );
+
+        response
+    }
+
+    // read_head returns the leading bytes of the file, used to check its content.
+    async fn read_head(path: &str) -> Result<Vec<u8>, StorageError> {
+        let mut head = Vec::with_capacity(SIGNATURE_LEN);
+        fs::File::open(path)
+            .await?
+            .take(SIGNATURE_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+
+        Ok(head)
+    }
+
+    // delete_orphan removes the file unless it was modified since `modified`, i.e. an upload
+    // was deduplicated into it. Returns false if the file was kept or doesn't exist.
+    pub async fn delete_orphan(
+        &self,
+        filename: &str,
+        modified: SystemTime,
+    ) -> Result<bool, StorageError> {
+        let file_path = format!("{}/{}", ASSETS_DIR, filename);
+        let aside_path = format!("{}/{}", TMP_DIR, uuid::Uuid::new_v4());
+
+        // the file is moved aside first, so uploads touching it from now on don't find it
+        // and store their own copy; a touch that happened before is seen in its metadata
+        match fs::rename(&file_path, &aside_path).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        if fs::metadata(&aside_path).await?.modified()? != modified {
+            // a copy stored by an upload in the meantime has the same content
+            fs::rename(&aside_path, &file_path).await?;
+
+            return Ok(false);
+        }
+
+        fs::remove_file(&aside_path).await?;
+
+        Ok(true)
+    }
+
+    // clean_tmp_files removes uploads left behind in TMP_DIR, e.g. by a crash mid-write.
+    // It must be called before the server starts accepting uploads.
+    pub async fn clean_tmp_files(&self) -> Result<usize, StorageError> {
+        let mut entries = fs::read_dir(TMP_DIR).await?;
+        let mut removed = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                fs::remove_file(entry.path()).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    // list_files returns all stored files, skipping hidden entries such as TMP_DIR.
+    pub async fn list_files(&self) -> Result<Vec<FileInfo>, StorageError> {
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(ASSETS_DIR).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+
+            let filename = match entry.file_name().into_string() {
+                Ok(filename) => filename,
+                Err(_) => continue,
+            };
+
+            if !metadata.is_file() || filename.starts_with('.') {
+                continue;
+            }
+
+            files.push(FileInfo {
+                filename,
+                modified: metadata.modified()?,
+            });
+        }
+
+        Ok(files)
+    }
+
+    pub async fn file_exists(&self, filename: &str) -> Result<bool, StorageError> {
+        let file_path = format!("{}/{}", ASSETS_DIR, filename);
+
+        Ok(exists(&file_path).await?)
+    }
 }
@@ -0,0 +1,67 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use serde_json::json;
+
+use super::{signing::SignatureQuery, Storage, StorageError};
+
+#[derive(thiserror::Error, Debug)]
+enum StorageApiError {
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ResponseError for StorageApiError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+
+        match self {
+            Self::NotFound => response.json(json!({ "message": "Asset not found" })),
+            Self::Forbidden => response.json(json!({ "message": "Invalid or expired signature" })),
+            Self::Internal(_) => response.json(json!({ "message": "Internal server error" })),
+        }
+    }
+}
+
+async fn get_asset(
+    req: HttpRequest,
+    filename: web::Path<String>,
+    query: web::Query<SignatureQuery>,
+    storage: web::Data<Storage>,
+) -> Result<HttpResponse, StorageApiError> {
+    // hidden entries (e.g. the tmp directory) and paths outside of ASSETS_DIR are never served
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return Err(StorageApiError::NotFound);
+    }
+
+    // in private mode every asset needs a valid signature
+    if !storage.authorize(req.path(), &query) {
+        return Err(StorageApiError::Forbidden);
+    }
+
+    let file = match storage.open_file(&filename).await {
+        Ok(file) => file,
+        Err(StorageError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(StorageApiError::NotFound)
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to open asset").into()),
+    };
+
+    Ok(storage.file_response(file, &req))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/assets/{filename}", web::get().to(get_asset));
+}
@@ -0,0 +1,86 @@
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use std::io::Cursor;
+
+// Decoding is limited, so a small file can't expand into a huge pixel buffer.
+const MAX_DECODED_SIZE: u64 = 256 * 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProcessingError {
+    #[error("Invalid image")]
+    Image(#[from] image::ImageError),
+
+    #[error("Invalid color profile")]
+    ColorProfile(#[from] moxcms::CmsError),
+}
+
+// normalize_image re-encodes the image without any metadata (EXIF, XMP, ICC),
+// with its orientation tag applied to the pixels and colors converted to sRGB.
+pub fn normalize_image(data: &[u8], format: ImageFormat) -> Result<Vec<u8>, ProcessingError> {
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(MAX_DECODED_SIZE);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let icc_profile = decoder.icc_profile()?;
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    if let Some(icc_profile) = icc_profile {
+        image = to_srgb(image, &icc_profile)?;
+    }
+
+    let mut encoded = Vec::new();
+
+    match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)
+            .encode_image(&image.to_rgb8())?,
+        _ => image.write_to(&mut Cursor::new(&mut encoded), format)?,
+    }
+
+    Ok(encoded)
+}
+
+// to_srgb converts pixels described by the embedded icc profile to sRGB.
+fn to_srgb(image: DynamicImage, icc_profile: &[u8]) -> Result<DynamicImage, ProcessingError> {
+    let profile = ColorProfile::new_from_slice(icc_profile)?;
+
+    // gray and CMYK images are already converted to RGB-like data by the decoder
+    if profile.color_space != DataColorSpace::Rgb {
+        return Ok(image);
+    }
+
+    let srgb = ColorProfile::new_srgb();
+
+    if image.color().has_alpha() {
+        let src = image.to_rgba8();
+        let mut dst = src.clone();
+
+        profile
+            .create_transform_8bit(
+                Layout::Rgba,
+                &srgb,
+                Layout::Rgba,
+                TransformOptions::default(),
+            )?
+            .transform(&src, &mut dst)?;
+
+        Ok(DynamicImage::ImageRgba8(dst))
+    } else {
+        let src = image.to_rgb8();
+        let mut dst = src.clone();
+
+        profile
+            .create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, TransformOptions::default())?
+            .transform(&src, &mut dst)?;
+
+        Ok(DynamicImage::ImageRgb8(dst))
+    }
+}